@@ -1,4 +1,10 @@
+use crate::gba::backup::FlashChip;
+use crate::gba::keypad::Button;
+use crate::ppu::ColorCorrection;
+use crate::renderer::parse_key_binding;
 use clap::Parser;
+use std::path::PathBuf;
+use winit::keyboard::KeyCode;
 use tracing_subscriber::filter::LevelFilter;
 
 #[derive(Parser, Debug)]
@@ -17,6 +23,24 @@ pub struct Args {
     // TODO: Add Logging Level
     #[arg(short, long)]
     pub log_level: Option<LogLevel>,
+    // Rebinds a GBA button in the GUI, e.g. `--bind a=KeyJ`
+    #[arg(long = "bind", value_name = "BUTTON=KEY", value_parser = parse_key_binding)]
+    pub bindings: Vec<(Button, KeyCode)>,
+    // How colors are converted for the screen, F2 cycles through them in the GUI
+    #[arg(long, value_enum, default_value_t = ColorCorrection::Raw)]
+    pub color_correction: ColorCorrection,
+    // Overrides the Flash chip picked from the ROM, for games that check its ID
+    #[arg(long, value_enum)]
+    pub flash_chip: Option<FlashChip>,
+    // How many frames the headless renderer runs for
+    #[arg(long, default_value_t = 60)]
+    pub frames: u32,
+    // Writes the sound of a headless run to a 16 bit PCM WAV file
+    #[arg(long, value_name = "PATH")]
+    pub wav: Option<PathBuf>,
+    // Also writes each sound channel to its own WAV file next to the one from `--wav`
+    #[arg(long, requires = "wav")]
+    pub stems: bool,
 }
 
 #[derive(Debug, clap::ValueEnum, Clone, Default)]
@@ -25,6 +49,7 @@ pub enum Renderer {
     Debug,
     Gui,
     Ratatui,
+    Headless,
 }
 
 #[derive(Debug, clap::ValueEnum, Clone, Default)]
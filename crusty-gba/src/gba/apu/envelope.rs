@@ -0,0 +1,137 @@
+use crate::utils::Bitable;
+
+/// Turns a channel off once it counts down to 0, if it's enabled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct LengthCounter {
+    pub enabled: bool,
+    counter: u32,
+    max: u32,
+}
+
+impl LengthCounter {
+    pub fn new(max: u32) -> Self {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    /// The length bits are how far the counter has already counted up to `max`
+    pub fn load(&mut self, length: u32) {
+        self.counter = self.max - length;
+    }
+
+    /// Restarting a channel that ran out plays it for the full length
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    /// Clocked at 256 Hz, returns true when the channel should be turned off
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+}
+
+/// Bits 8-15 of the register the envelope is in
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct EnvelopeControl {
+    pub step_time: u32,
+    pub increase: bool,
+    pub initial_volume: u32,
+}
+
+impl From<u32> for EnvelopeControl {
+    fn from(value: u32) -> Self {
+        EnvelopeControl {
+            step_time: (value >> 8) & 0b111,
+            increase: value.bit_is_high(11),
+            initial_volume: value.half_byte_at(12),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct Envelope {
+    pub control: EnvelopeControl,
+    pub volume: u32,
+    timer: u32,
+}
+
+impl Envelope {
+    /// The channel's DAC is off when the envelope can only ever be silent
+    pub fn dac_enabled(&self) -> bool {
+        self.control.initial_volume != 0 || self.control.increase
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.control.initial_volume;
+        self.timer = self.control.step_time;
+    }
+
+    /// Clocked at 64 Hz, a step time of 0 stops the envelope
+    pub fn clock(&mut self) {
+        if self.control.step_time == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+
+        self.timer = self.control.step_time;
+        if self.control.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.control.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{Envelope, EnvelopeControl, LengthCounter};
+
+    #[test]
+    fn length_counter_turns_off_when_enabled() {
+        let mut length = LengthCounter::new(64);
+        length.load(62);
+        assert!(!length.clock());
+
+        length.enabled = true;
+        assert!(!length.clock());
+        assert!(length.clock());
+        // It stays off until the channel is triggered again
+        assert!(!length.clock());
+        length.trigger();
+        assert!((0..63).all(|_| !length.clock()));
+        assert!(length.clock());
+    }
+
+    #[test]
+    fn envelope_steps_volume() {
+        // Starts at 2, decreasing every 3 clocks
+        let mut envelope = Envelope {
+            control: EnvelopeControl::from(0x2300),
+            ..Envelope::default()
+        };
+        envelope.trigger();
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(2, envelope.volume);
+        envelope.clock();
+        assert_eq!(1, envelope.volume);
+        (0..6).for_each(|_| envelope.clock());
+        assert_eq!(0, envelope.volume);
+
+        assert!(envelope.dac_enabled());
+        envelope.control = EnvelopeControl::from(0x0700);
+        assert!(!envelope.dac_enabled());
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+
+use crate::utils::Bitable;
+
+const FIFO_SIZE: usize = 32;
+// Sound DMA is requested once half of the FIFO has been played
+const FIFO_REFILL: usize = 16;
+
+/// The settings for one of the Direct Sound channels in SOUNDCNT_H
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct DirectSoundControl {
+    /// 100% volume, otherwise it's 50%
+    pub full_volume: bool,
+    pub right: bool,
+    pub left: bool,
+    /// Timer 0 or 1 plays the samples
+    pub timer: usize,
+}
+
+impl DirectSoundControl {
+    /// `fifo` is 0 for FIFO A and 1 for FIFO B
+    pub fn new(sound_cnt_h: u32, fifo: usize) -> Self {
+        let shift = 8 + fifo as u32 * 4;
+        DirectSoundControl {
+            full_volume: sound_cnt_h.bit_is_high(2 + fifo as u32),
+            right: sound_cnt_h.bit_is_high(shift),
+            left: sound_cnt_h.bit_is_high(shift + 1),
+            timer: sound_cnt_h.bit_is_high(shift + 2) as usize,
+        }
+    }
+}
+
+/// A Direct Sound FIFO of signed 8 bit samples, filled by the CPU or sound DMA
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(super) struct Fifo {
+    pub control: DirectSoundControl,
+    samples: VecDeque<i8>,
+    /// The sample being played
+    pub output: i8,
+}
+
+impl Fifo {
+    /// Queues a sample written by the CPU or sound DMA
+    pub fn push(&mut self, sample: i8) {
+        // NOTE: Samples written to a full FIFO are dropped
+        if self.samples.len() < FIFO_SIZE {
+            self.samples.push_back(sample);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.output = 0;
+    }
+
+    /// Plays the next sample, the last one keeps playing if the FIFO is empty. Returns
+    /// true when the FIFO needs to be refilled
+    pub fn pop(&mut self) -> bool {
+        if let Some(sample) = self.samples.pop_front() {
+            self.output = sample;
+        }
+        self.samples.len() <= FIFO_REFILL
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{DirectSoundControl, Fifo};
+
+    #[test]
+    fn direct_sound_control() {
+        // A is full volume on the right with timer 1, B is on the left with timer 0
+        let control = 0x2504;
+        assert_eq!(
+            DirectSoundControl { full_volume: true, right: true, left: false, timer: 1 },
+            DirectSoundControl::new(control, 0)
+        );
+        assert_eq!(
+            DirectSoundControl { full_volume: false, right: false, left: true, timer: 0 },
+            DirectSoundControl::new(control, 1)
+        );
+    }
+
+    #[test]
+    fn fifo_pops_and_requests_refill() {
+        let mut fifo = Fifo::default();
+        for _ in 0..9 {
+            fifo.push(-1);
+            fifo.push(-128);
+        }
+        assert_eq!(18, fifo.samples.len());
+
+        assert!(!fifo.pop());
+        assert_eq!(-1, fifo.output);
+        assert!(fifo.pop());
+        assert_eq!(-128, fifo.output);
+
+        // An empty FIFO keeps playing the last sample
+        fifo.reset();
+        fifo.push(5);
+        fifo.push(0);
+        fifo.pop();
+        fifo.pop();
+        fifo.pop();
+        assert_eq!(0, fifo.output);
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+
+use super::fifo::Fifo;
+use super::FIFOS;
+use crate::utils::Bitable;
+
+// The CPU runs at 16.78 MHz
+const CPU_FREQUENCY: u32 = 1 << 24;
+/// The rate of the samples in the `SampleBuffer`
+pub const SAMPLE_RATE: u32 = 48000;
+// About a third of a second, the frontend is expected to drain it every frame
+const SAMPLE_BUFFER_SIZE: usize = 0x4000;
+const PSG_CHANNELS: usize = 4;
+/// The PSG channels 1 to 4 followed by FIFO A and B
+pub const CHANNELS: usize = PSG_CHANNELS + FIFOS;
+// The 10 bit output of the mixer, the bias centers it
+const MIXER_MAX: i32 = 0x3ff;
+const MIXER_CENTER: i32 = 0x200;
+
+/// SOUNDCNT_L, the PSG master volumes and which channels play on each side
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct PsgControl {
+    /// 0 to 7, for the right and left side
+    pub volume: [u32; 2],
+    /// Bit 0 is channel 1, for the right and left side
+    pub enabled: [u32; 2],
+}
+
+impl From<u32> for PsgControl {
+    fn from(value: u32) -> Self {
+        PsgControl {
+            volume: [value & 0b111, (value >> 4) & 0b111],
+            enabled: [value.half_byte_at(8), value.half_byte_at(12)],
+        }
+    }
+}
+
+/// SOUNDBIAS, the resolution trades amplitude bits for a higher sample rate
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct SoundBias {
+    pub level: i32,
+    pub resolution: u32,
+}
+
+impl From<u32> for SoundBias {
+    fn from(value: u32) -> Self {
+        SoundBias {
+            level: (value & 0x3fe) as i32,
+            resolution: (value >> 14) & 0b11,
+        }
+    }
+}
+
+impl SoundBias {
+    /// The cycles between two samples, 32.768 kHz at a resolution of 0 up to 262.144 kHz at 3
+    pub fn sample_period(&self) -> u32 {
+        512 >> self.resolution
+    }
+
+    /// Biases, clips and quantizes the mixed `sample` to 9 to 6 bits, then scales it to 16 bits
+    fn output(&self, sample: i32) -> i16 {
+        let sample = (sample + self.level).clamp(0, MIXER_MAX);
+        let sample = sample & !((1 << (self.resolution + 1)) - 1);
+        ((sample - MIXER_CENTER) << 6) as i16
+    }
+}
+
+/// A ring buffer of stereo samples at `SAMPLE_RATE`, left first. Once it's full the oldest
+/// samples are dropped
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleBuffer {
+    samples: VecDeque<[i16; 2]>,
+}
+
+impl Default for SampleBuffer {
+    fn default() -> Self {
+        SampleBuffer {
+            samples: VecDeque::with_capacity(SAMPLE_BUFFER_SIZE),
+        }
+    }
+}
+
+impl SampleBuffer {
+    pub fn push(&mut self, sample: [i16; 2]) {
+        if self.samples.len() == SAMPLE_BUFFER_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Takes every sample in the buffer, oldest first
+    pub fn drain(&mut self) -> impl Iterator<Item = [i16; 2]> + '_ {
+        self.samples.drain(..)
+    }
+}
+
+/// Converts from the rate set in SOUNDBIAS to `SAMPLE_RATE` by linear interpolation
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Resampler {
+    previous: [i16; 2],
+    /// How far the next output sample is past `previous`, an input sample is `SAMPLE_RATE` long
+    phase: u32,
+}
+
+impl Resampler {
+    fn push(&mut self, sample: [i16; 2], rate: u32, buffer: &mut SampleBuffer) {
+        while self.phase < SAMPLE_RATE {
+            let t = self.phase as i32;
+            let interpolate = |i: usize| {
+                let (a, b) = (self.previous[i] as i32, sample[i] as i32);
+                (a + (b - a) * t / SAMPLE_RATE as i32) as i16
+            };
+            buffer.push([interpolate(0), interpolate(1)]);
+            self.phase += rate;
+        }
+        self.phase -= SAMPLE_RATE;
+        self.previous = sample;
+    }
+}
+
+/// Mixes the PSG channels and the Direct Sound FIFOs down to stereo samples
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Mixer {
+    pub psg_control: PsgControl,
+    /// 25%, 50% or 100% from SOUNDCNT_H
+    pub psg_volume: u32,
+    pub bias: SoundBias,
+    timer: u32,
+    resampler: Resampler,
+    pub buffer: SampleBuffer,
+    /// Each channel mixed on its own, only recorded when asked for
+    stem_resamplers: Vec<Resampler>,
+    pub stems: Vec<SampleBuffer>,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        let bias = SoundBias::default();
+        Mixer {
+            psg_control: PsgControl::default(),
+            psg_volume: 0,
+            bias,
+            timer: bias.sample_period(),
+            resampler: Resampler::default(),
+            buffer: SampleBuffer::default(),
+            stem_resamplers: Vec::new(),
+            stems: Vec::new(),
+        }
+    }
+}
+
+impl Mixer {
+    /// The cycles until the next sample is due
+    pub fn until_sample(&self) -> u32 {
+        self.timer
+    }
+
+    /// Counts down to the next sample, returns true when it's due
+    pub fn run(&mut self, cycles: u32) -> bool {
+        self.timer -= cycles;
+        if self.timer > 0 {
+            return false;
+        }
+        self.timer = self.bias.sample_period();
+        true
+    }
+
+    /// Starts filling a buffer in `stems` for each channel
+    pub fn record_stems(&mut self) {
+        self.stem_resamplers = vec![Resampler::default(); CHANNELS];
+        self.stems = vec![SampleBuffer::default(); CHANNELS];
+    }
+
+    /// The signed contribution of each channel to the right and left side
+    pub fn levels(&self, psg: [u32; PSG_CHANNELS], fifos: &[Fifo; FIFOS]) -> [[i32; 2]; CHANNELS] {
+        let mut levels = [[0; 2]; CHANNELS];
+        // NOTE: A PSG volume of 3 is prohibited, it's treated as 100%
+        let psg_shift = 2 - self.psg_volume.min(2);
+        for (channel, output) in psg.into_iter().enumerate() {
+            for (side, level) in levels[channel].iter_mut().enumerate() {
+                if self.psg_control.enabled[side].bit_is_high(channel as u32) {
+                    let volume = self.psg_control.volume[side] + 1;
+                    *level = ((output * volume) >> psg_shift) as i32;
+                }
+            }
+        }
+        for (i, fifo) in fifos.iter().enumerate() {
+            let control = fifo.control;
+            let sample = fifo.output as i32 * if control.full_volume { 4 } else { 2 };
+            levels[PSG_CHANNELS + i] = [
+                if control.right { sample } else { 0 },
+                if control.left { sample } else { 0 },
+            ];
+        }
+        levels
+    }
+
+    /// Mixes the channel `levels` into the next sample and resamples it into the buffer
+    pub fn push(&mut self, levels: [[i32; 2]; CHANNELS]) {
+        let mix = |side: usize| self.bias.output(levels.iter().map(|l| l[side]).sum());
+        // The buffer is left first
+        let sample = [mix(1), mix(0)];
+        let rate = CPU_FREQUENCY / self.bias.sample_period();
+        self.resampler.push(sample, rate, &mut self.buffer);
+
+        let stems = self.stem_resamplers.iter_mut().zip(self.stems.iter_mut());
+        for ((resampler, buffer), level) in stems.zip(levels) {
+            let sample = [self.bias.output(level[1]), self.bias.output(level[0])];
+            resampler.push(sample, rate, buffer);
+        }
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{
+        Mixer, PsgControl, Resampler, SampleBuffer, SoundBias, CHANNELS, SAMPLE_BUFFER_SIZE,
+    };
+    use crate::gba::apu::fifo::{DirectSoundControl, Fifo};
+
+    #[test]
+    fn sound_bias_output() {
+        let bias = SoundBias::from(0x200);
+        assert_eq!(0, bias.output(0));
+        assert_eq!(0x1fe << 6, bias.output(0x1fe) as i32);
+        // Clipped at both ends
+        assert_eq!(i16::MIN, bias.output(-0x400));
+        assert_eq!(0x1fe << 6, bias.output(0x400) as i32);
+
+        // 6 bits at 262.144 kHz
+        let bias = SoundBias::from(0xc200);
+        assert_eq!(64, bias.sample_period());
+        assert_eq!(0x1f0 << 6, bias.output(0x1ff) as i32);
+    }
+
+    #[test]
+    fn sample_buffer_drops_oldest() {
+        let mut buffer = SampleBuffer::default();
+        for i in 0..SAMPLE_BUFFER_SIZE + 1 {
+            buffer.push([i as i16, 0]);
+        }
+        let samples: Vec<_> = buffer.drain().collect();
+        assert_eq!(SAMPLE_BUFFER_SIZE, samples.len());
+        assert_eq!([1, 0], samples[0]);
+        assert_eq!(0, buffer.drain().count());
+    }
+
+    #[test]
+    fn resampler_interpolates() {
+        let mut resampler = Resampler::default();
+        let mut buffer = SampleBuffer::default();
+        // Twice the output rate
+        resampler.push([100, -100], 96000, &mut buffer);
+        assert_eq!(vec![[0, 0]], buffer.drain().collect::<Vec<_>>());
+        resampler.push([200, -200], 96000, &mut buffer);
+        assert_eq!(0, buffer.drain().count());
+        resampler.push([300, -300], 96000, &mut buffer);
+        assert_eq!(vec![[200, -200]], buffer.drain().collect::<Vec<_>>());
+
+        // 32768 Hz makes 48000 Hz
+        let mut resampler = Resampler::default();
+        let mut buffer = SampleBuffer::default();
+        let mut samples = 0;
+        for _ in 0..32768 {
+            resampler.push([0, 0], 32768, &mut buffer);
+            samples += buffer.drain().count();
+        }
+        assert_eq!(48000, samples);
+    }
+
+    #[test]
+    fn mixer_levels() {
+        let mut mixer = Mixer {
+            // Channel 2 on the right at volume 7, on the left at volume 3
+            psg_control: PsgControl::from(0x2237),
+            psg_volume: 2,
+            ..Mixer::default()
+        };
+        let mut fifos = [Fifo::default(), Fifo::default()];
+        fifos[1].control = DirectSoundControl::new(0x3000, 1);
+        fifos[1].output = -128;
+
+        let levels = mixer.levels([15, 15, 15, 15], &fifos);
+        assert_eq!([0, 0], levels[0]);
+        assert_eq!([120, 60], levels[1]);
+        assert_eq!([0, 0], levels[4]);
+        assert_eq!([-256, -256], levels[5]);
+
+        mixer.psg_volume = 0;
+        assert_eq!([30, 15], mixer.levels([15, 15, 15, 15], &fifos)[1]);
+    }
+
+    #[test]
+    fn mixer_records_stems() {
+        let mut mixer = Mixer {
+            bias: SoundBias::from(0x200),
+            ..Mixer::default()
+        };
+        let mut levels = [[0; 2]; CHANNELS];
+        levels[0] = [16, 0];
+        levels[5] = [0, -32];
+        mixer.push(levels);
+        assert!(mixer.stems.is_empty());
+
+        mixer.record_stems();
+        mixer.push(levels);
+        mixer.push(levels);
+        assert_eq!(Some([-32 << 6, 16 << 6]), mixer.buffer.drain().last());
+        assert_eq!(Some([0, 16 << 6]), mixer.stems[0].drain().last());
+        assert_eq!(Some([-32 << 6, 0]), mixer.stems[5].drain().last());
+        assert!(mixer.stems[1].drain().all(|s| s == [0, 0]));
+    }
+}
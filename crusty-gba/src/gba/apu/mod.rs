@@ -0,0 +1,344 @@
+mod envelope;
+mod fifo;
+mod mixer;
+mod noise;
+mod square;
+mod wave;
+
+use crate::utils::io_registers::{
+    FIFO_A, SOUND_1_CNT_H, SOUND_1_CNT_L, SOUND_1_CNT_X, SOUND_2_CNT_H, SOUND_2_CNT_L,
+    SOUND_3_CNT_H, SOUND_3_CNT_L, SOUND_3_CNT_X, SOUND_4_CNT_H, SOUND_4_CNT_L, SOUND_BIAS,
+    SOUND_CNT_H, SOUND_CNT_L, SOUND_CNT_X, WAVE_RAM,
+};
+use crate::utils::Bitable;
+use fifo::{DirectSoundControl, Fifo};
+use mixer::{Mixer, PsgControl, SoundBias};
+pub use mixer::{SampleBuffer, CHANNELS, SAMPLE_RATE};
+use noise::Noise;
+use square::Square;
+use wave::Wave;
+
+pub(super) const WAVE_RAM_SIZE: usize = 0x10;
+pub(super) const FIFOS: usize = 2;
+// The frame sequencer runs at 512 Hz
+const FRAME_SEQUENCER_CYCLES: u32 = 0x8000;
+
+/// Counts `timer` down by `cycles`, reloading it with `period` every time it runs out.
+/// Returns the number of times it ran out
+fn run_timer(timer: &mut u32, period: u32, cycles: u32) -> u32 {
+    if cycles < *timer {
+        *timer -= cycles;
+        return 0;
+    }
+
+    let remaining = cycles - *timer;
+    *timer = period - remaining % period;
+    1 + remaining / period
+}
+
+/// The four legacy sound channels from the Game Boy and the two Direct Sound FIFOs
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Apu {
+    /// The master enable in SOUNDCNT_X, the PSG registers can't be written while it's off
+    pub enabled: bool,
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    fifos: [Fifo; FIFOS],
+    mixer: Mixer,
+    sequencer_cycles: u32,
+    sequencer_step: u32,
+    old_cycle: u32,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu {
+            enabled: false,
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            fifos: [Fifo::default(), Fifo::default()],
+            mixer: Mixer::default(),
+            sequencer_cycles: 0,
+            sequencer_step: 0,
+            old_cycle: 0,
+        }
+    }
+}
+
+impl Apu {
+    /// Handles a write to one of the sound registers, `address` is the address of a halfword
+    pub fn write(&mut self, address: usize, value: u32) {
+        if address == SOUND_CNT_X {
+            self.enabled = value.bit_is_high(7);
+            if !self.enabled {
+                // The PSG is reset, all but wave RAM
+                self.square1 = Square::new(true);
+                self.square2 = Square::new(false);
+                self.wave.reset();
+                self.noise = Noise::new();
+                self.mixer.psg_control = PsgControl::default();
+                self.sequencer_cycles = 0;
+                self.sequencer_step = 0;
+            }
+            return;
+        }
+        if address == SOUND_CNT_H {
+            self.mixer.psg_volume = value & 0b11;
+            self.write_direct_sound_control(value);
+            return;
+        }
+        if address == SOUND_BIAS {
+            self.mixer.bias = SoundBias::from(value);
+            return;
+        }
+        if (WAVE_RAM..WAVE_RAM + WAVE_RAM_SIZE).contains(&address) {
+            self.wave.write_ram(address - WAVE_RAM, value);
+            return;
+        }
+        if !self.enabled {
+            return;
+        }
+
+        match address {
+            SOUND_1_CNT_L => self.square1.write_sweep(value),
+            SOUND_1_CNT_H => self.square1.write_duty_envelope(value),
+            SOUND_1_CNT_X => self.square1.write_frequency(value),
+            SOUND_2_CNT_L => self.square2.write_duty_envelope(value),
+            SOUND_2_CNT_H => self.square2.write_frequency(value),
+            SOUND_3_CNT_L => self.wave.write_select(value),
+            SOUND_3_CNT_H => self.wave.write_length_volume(value),
+            SOUND_3_CNT_X => self.wave.write_frequency(value),
+            SOUND_4_CNT_L => self.noise.write_length_envelope(value),
+            SOUND_4_CNT_H => self.noise.write_control(value),
+            SOUND_CNT_L => self.mixer.psg_control = PsgControl::from(value),
+            _ => {}
+        }
+    }
+
+    /// SOUNDCNT_H, the bits for FIFO B are 4 above the ones for FIFO A
+    fn write_direct_sound_control(&mut self, value: u32) {
+        for (i, fifo) in self.fifos.iter_mut().enumerate() {
+            fifo.control = DirectSoundControl::new(value, i);
+            if value.bit_is_high(11 + i as u32 * 4) {
+                fifo.reset();
+            }
+        }
+    }
+
+    /// Queues the bytes of a halfword written to FIFO_A or FIFO_B, `written` masks the
+    /// bytes that were actually written
+    pub fn write_fifo(&mut self, address: usize, value: u32, written: u32) {
+        let fifo = &mut self.fifos[(address - FIFO_A) / 4];
+        for shift in [0, 8] {
+            if written & (0xff << shift) != 0 {
+                fifo.push((value >> shift) as i8);
+            }
+        }
+    }
+
+    /// Plays the next samples of the FIFOs driven by `timer`, once for each of its
+    /// `overflows`. Returns which FIFOs need sound DMA to refill them
+    pub fn timer_overflow(&mut self, timer: usize, overflows: u32) -> [bool; FIFOS] {
+        let mut refill = [false; FIFOS];
+        for (i, fifo) in self.fifos.iter_mut().enumerate() {
+            if fifo.control.timer != timer {
+                continue;
+            }
+            for _ in 0..overflows {
+                refill[i] = fifo.pop();
+            }
+        }
+        refill
+    }
+
+    /// Runs the channels up to `cycle`, mixing their output into the sample buffer
+    pub fn tick(&mut self, cycle: u32) {
+        let delta_cycle = cycle.wrapping_sub(self.old_cycle);
+        self.old_cycle = cycle;
+
+        // The channels are run up to each frame sequencer step and each sample, so length
+        // and envelope changes happen at the right sample
+        let mut remaining = delta_cycle;
+        while remaining > 0 {
+            let mut cycles = remaining.min(self.mixer.until_sample());
+            if self.enabled {
+                cycles = cycles.min(FRAME_SEQUENCER_CYCLES - self.sequencer_cycles);
+                self.run_channels(cycles);
+                self.sequencer_cycles += cycles;
+                if self.sequencer_cycles == FRAME_SEQUENCER_CYCLES {
+                    self.sequencer_cycles = 0;
+                    self.step_frame_sequencer();
+                }
+            }
+            remaining -= cycles;
+
+            if self.mixer.run(cycles) {
+                self.mixer.push(self.levels());
+            }
+        }
+    }
+
+    /// What each channel adds to the mix, everything is silent while the APU is off
+    fn levels(&self) -> [[i32; 2]; CHANNELS] {
+        if !self.enabled {
+            return [[0; 2]; CHANNELS];
+        }
+        self.mixer.levels(self.outputs(), &self.fifos)
+    }
+
+    fn run_channels(&mut self, cycles: u32) {
+        self.square1.run(cycles);
+        self.square2.run(cycles);
+        self.wave.run(cycles);
+        self.noise.run(cycles);
+    }
+
+    /// Length counters are clocked on even steps, the sweep on steps 2 and 6, and the
+    /// envelopes on step 7
+    fn step_frame_sequencer(&mut self) {
+        if self.sequencer_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+
+    /// The wave RAM bank being played
+    pub fn wave_bank(&self) -> usize {
+        self.wave.bank
+    }
+
+    /// The halfword of wave RAM the CPU sees at `offset`
+    pub fn read_wave_ram(&self, offset: usize) -> u32 {
+        self.wave.read_ram(offset)
+    }
+
+    /// The sound on flags of SOUNDCNT_X, bit 0 is channel 1
+    pub fn channels_on(&self) -> u32 {
+        [
+            self.square1.enabled,
+            self.square2.enabled,
+            self.wave.enabled,
+            self.noise.enabled,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, on)| acc | ((*on as u32) << i))
+    }
+
+    /// The current 4 bit sample of each channel
+    pub fn outputs(&self) -> [u32; 4] {
+        [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ]
+    }
+
+    /// The mixed samples at `SAMPLE_RATE`
+    pub fn samples(&mut self) -> &mut SampleBuffer {
+        &mut self.mixer.buffer
+    }
+
+    /// Each channel mixed on its own, empty until `record_stems` is called
+    pub fn stems(&mut self) -> &mut [SampleBuffer] {
+        &mut self.mixer.stems
+    }
+
+    pub fn record_stems(&mut self) {
+        self.mixer.record_stems();
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{run_timer, Apu, FRAME_SEQUENCER_CYCLES};
+    use crate::utils::io_registers::{
+        FIFO_A, FIFO_B, SOUND_2_CNT_H, SOUND_2_CNT_L, SOUND_BIAS, SOUND_CNT_H, SOUND_CNT_X,
+    };
+
+    #[test]
+    fn run_timer_reloads() {
+        let mut timer = 10;
+        assert_eq!(0, run_timer(&mut timer, 16, 9));
+        assert_eq!(1, timer);
+        assert_eq!(3, run_timer(&mut timer, 16, 33));
+        assert_eq!(16, timer);
+    }
+
+    #[test]
+    fn apu_length_runs_out() {
+        let mut apu = Apu::default();
+        // Writes are ignored until the APU is turned on
+        apu.write(SOUND_2_CNT_L, 0xf03f);
+        assert_eq!(0, apu.channels_on());
+
+        apu.write(SOUND_CNT_X, 0x80);
+        // A length of 1 with the length counter enabled
+        apu.write(SOUND_2_CNT_L, 0xf03f);
+        apu.write(SOUND_2_CNT_H, 0xc000);
+        assert_eq!(0b10, apu.channels_on());
+        assert_eq!(15, apu.outputs()[1]);
+
+        // The first frame sequencer step clocks the length
+        apu.tick(FRAME_SEQUENCER_CYCLES - 1);
+        assert_eq!(0b10, apu.channels_on());
+        apu.tick(FRAME_SEQUENCER_CYCLES);
+        assert_eq!(0, apu.channels_on());
+
+        apu.write(SOUND_2_CNT_H, 0x8000);
+        apu.write(SOUND_CNT_X, 0);
+        assert_eq!(0, apu.channels_on());
+    }
+
+    #[test]
+    fn apu_fifos_follow_their_timers() {
+        let mut apu = Apu::default();
+        // FIFO A on timer 0, FIFO B on timer 1
+        apu.write(SOUND_CNT_H, 0x4000);
+        apu.write_fifo(FIFO_A, 0x0201, 0xffff);
+        apu.write_fifo(FIFO_B, 0x0403, 0xffff);
+
+        assert_eq!([true, false], apu.timer_overflow(0, 1));
+        assert_eq!([1, 0], [apu.fifos[0].output, apu.fifos[1].output]);
+        assert_eq!([false, true], apu.timer_overflow(1, 2));
+        assert_eq!([1, 4], [apu.fifos[0].output, apu.fifos[1].output]);
+
+        // Resetting FIFO A leaves B alone
+        apu.write(SOUND_CNT_H, 0x4800);
+        assert_eq!([0, 4], [apu.fifos[0].output, apu.fifos[1].output]);
+    }
+
+    #[test]
+    fn apu_mixes_samples() {
+        let mut apu = Apu::default();
+        apu.write(SOUND_BIAS, 0x200);
+        // Silence is still mixed while the APU is off, 64 samples at 32768 Hz
+        apu.tick(512 * 64);
+        assert_eq!(94, apu.samples().drain().count());
+        assert!(apu.samples().drain().all(|s| s == [0, 0]));
+
+        // FIFO A at full volume on the left
+        apu.write(SOUND_CNT_X, 0x80);
+        apu.write(SOUND_CNT_H, 0x0204);
+        apu.write_fifo(FIFO_A, 0x0040, 0xffff);
+        apu.timer_overflow(0, 1);
+        apu.tick(512 * 128);
+        assert_eq!(Some([0x100 << 6, 0]), apu.samples().drain().last());
+    }
+}
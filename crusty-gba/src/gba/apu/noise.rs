@@ -0,0 +1,119 @@
+use super::envelope::{Envelope, EnvelopeControl, LengthCounter};
+use super::run_timer;
+use crate::utils::Bitable;
+
+/// The noise channel 4, which plays the low bit of a 15 or 7 bit LFSR
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Noise {
+    pub enabled: bool,
+    divisor: u32,
+    short_width: bool,
+    shift: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    lfsr: u32,
+    timer: u32,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        let mut noise = Noise {
+            enabled: false,
+            divisor: 0,
+            short_width: false,
+            shift: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            lfsr: 0,
+            timer: 0,
+        };
+        noise.timer = noise.period();
+        noise
+    }
+
+    /// The period of one LFSR shift in cycles, a divisor of 0 counts as 0.5
+    fn period(&self) -> u32 {
+        let divisor = if self.divisor == 0 { 8 } else { self.divisor * 16 };
+        (divisor << self.shift) * 4
+    }
+
+    /// SOUND4CNT_L
+    pub fn write_length_envelope(&mut self, value: u32) {
+        self.length.load(value & 0x3f);
+        self.envelope.control = EnvelopeControl::from(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    /// SOUND4CNT_H
+    pub fn write_control(&mut self, value: u32) {
+        self.divisor = value & 0b111;
+        self.short_width = value.bit_is_high(3);
+        self.shift = value.half_byte_at(4);
+        self.length.enabled = value.bit_is_high(14);
+        if value.bit_is_high(15) {
+            self.enabled = self.envelope.dac_enabled();
+            self.length.trigger();
+            self.envelope.trigger();
+            self.lfsr = if self.short_width { 0x7f } else { 0x7fff };
+            self.timer = self.period();
+        }
+    }
+
+    pub fn run(&mut self, cycles: u32) {
+        let period = self.period();
+        let steps = run_timer(&mut self.timer, period, cycles);
+        let width = if self.short_width { 7 } else { 15 };
+        for _ in 0..steps {
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << (width - 1));
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// The current 4 bit sample, the output is high when the low bit is 0
+    pub fn output(&self) -> u32 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::Noise;
+
+    #[test]
+    fn noise_lfsr_widths() {
+        let mut noise = Noise::new();
+        noise.write_length_envelope(0xf000);
+        // Divisor 1, 64 cycles a shift
+        noise.write_control(0x8001);
+        assert_eq!(0, noise.output());
+
+        // All ones shifts in 0s once the low bits start to differ
+        noise.run(64 * 14);
+        assert_eq!(0x0001, noise.lfsr);
+        noise.run(64);
+        assert_eq!(0x4000, noise.lfsr);
+        assert_eq!(15, noise.output());
+
+        // The 7 bit LFSR repeats every 127 shifts
+        noise.write_control(0x8009);
+        let start = noise.lfsr;
+        noise.run(64 * 127);
+        assert_eq!(start, noise.lfsr);
+    }
+}
@@ -0,0 +1,217 @@
+use super::envelope::{Envelope, EnvelopeControl, LengthCounter};
+use super::run_timer;
+use crate::utils::Bitable;
+
+// Which of the 8 steps of a wave are high for 12.5%, 25%, 50% and 75% duty
+const DUTY_CYCLES: [u32; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+const MAX_FREQUENCY: u32 = 2047;
+
+/// The period of one duty step in cycles
+fn square_period(frequency: u32) -> u32 {
+    (2048 - frequency) * 16
+}
+
+/// Channel 1's frequency sweep, from SOUND1CNT_L
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Sweep {
+    shift: u32,
+    decrease: bool,
+    period: u32,
+    enabled: bool,
+    shadow: u32,
+    timer: u32,
+}
+
+impl Sweep {
+    fn write(&mut self, value: u32) {
+        self.shift = value & 0b111;
+        self.decrease = value.bit_is_high(3);
+        self.period = (value >> 4) & 0b111;
+    }
+
+    fn reload_timer(&mut self) {
+        // NOTE: A period of 0 is treated as 8 by the timer
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn next_frequency(&self) -> u32 {
+        let delta = self.shadow >> self.shift;
+        if self.decrease {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+/// The square wave channels 1 and 2, only channel 1 has a sweep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Square {
+    pub enabled: bool,
+    duty: u32,
+    frequency: u32,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+    duty_step: u32,
+    timer: u32,
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Self {
+        Square {
+            enabled: false,
+            duty: 0,
+            frequency: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::default(),
+            sweep: has_sweep.then(Sweep::default),
+            duty_step: 0,
+            timer: square_period(0),
+        }
+    }
+
+    /// SOUND1CNT_L
+    pub fn write_sweep(&mut self, value: u32) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.write(value);
+        }
+    }
+
+    /// SOUND1CNT_H and SOUND2CNT_L
+    pub fn write_duty_envelope(&mut self, value: u32) {
+        self.length.load(value & 0x3f);
+        self.duty = (value >> 6) & 0b11;
+        self.envelope.control = EnvelopeControl::from(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    /// SOUND1CNT_X and SOUND2CNT_H
+    pub fn write_frequency(&mut self, value: u32) {
+        self.frequency = value & 0x7ff;
+        self.length.enabled = value.bit_is_high(14);
+        if value.bit_is_high(15) {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.timer = square_period(self.frequency);
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            // The overflow check happens right away
+            if sweep.shift != 0 && sweep.next_frequency() > MAX_FREQUENCY {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn run(&mut self, cycles: u32) {
+        let steps = run_timer(&mut self.timer, square_period(self.frequency), cycles);
+        self.duty_step = (self.duty_step + steps) % 8;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Clocked at 128 Hz
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+        let frequency = sweep.next_frequency();
+        if frequency > MAX_FREQUENCY {
+            self.enabled = false;
+        } else if sweep.shift != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+            // The new frequency is checked again, but not used
+            if sweep.next_frequency() > MAX_FREQUENCY {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// The current 4 bit sample
+    pub fn output(&self) -> u32 {
+        if self.enabled && DUTY_CYCLES[self.duty as usize].bit_is_high(self.duty_step) {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::Square;
+
+    #[test]
+    fn square_duty_and_envelope() {
+        let mut square = Square::new(false);
+        // 50% duty at volume 10, frequency 2047 is 16 cycles a step
+        square.write_duty_envelope(0xa080);
+        square.write_frequency(0x87ff);
+        assert!(square.enabled);
+
+        let outputs: Vec<u32> = (0..8)
+            .map(|_| {
+                square.run(16);
+                square.output()
+            })
+            .collect();
+        assert_eq!(vec![10, 10, 0, 0, 0, 0, 10, 10], outputs);
+    }
+
+    #[test]
+    fn square_sweep_overflow_disables() {
+        let mut square = Square::new(true);
+        // Period 1, increasing by frequency >> 1
+        square.write_sweep(0x11);
+        square.write_duty_envelope(0xf000);
+        square.write_frequency(0x8500);
+        assert!(square.enabled);
+
+        square.clock_sweep();
+        assert_eq!(0x500 + 0x280, square.frequency);
+        // 0x780 + 0x3c0 is over 2047
+        assert!(!square.enabled);
+    }
+
+    #[test]
+    fn square_dac_off_disables() {
+        let mut square = Square::new(false);
+        square.write_duty_envelope(0xf000);
+        square.write_frequency(0x8000);
+        assert!(square.enabled);
+
+        square.write_duty_envelope(0x0000);
+        assert!(!square.enabled);
+        square.write_frequency(0x8000);
+        assert!(!square.enabled);
+    }
+}
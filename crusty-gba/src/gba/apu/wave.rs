@@ -0,0 +1,166 @@
+use super::envelope::LengthCounter;
+use super::run_timer;
+use crate::utils::Bitable;
+
+// Each bank is 16 bytes of 4 bit samples, played from the high nibble first
+const BANK_SIZE: usize = 16;
+const BANK_SAMPLES: u32 = 32;
+
+/// The period of one sample in cycles
+fn wave_period(frequency: u32) -> u32 {
+    (2048 - frequency) * 8
+}
+
+/// The wave channel 3, which plays the samples in wave RAM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Wave {
+    pub enabled: bool,
+    dac_enabled: bool,
+    /// Plays both banks as one 64 sample wave
+    two_banks: bool,
+    /// The bank being played, the CPU can only access the other one
+    pub bank: usize,
+    volume: u32,
+    force_volume: bool,
+    frequency: u32,
+    length: LengthCounter,
+    ram: [u8; BANK_SIZE * 2],
+    position: u32,
+    timer: u32,
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            two_banks: false,
+            bank: 0,
+            volume: 0,
+            force_volume: false,
+            frequency: 0,
+            length: LengthCounter::new(256),
+            ram: [0; BANK_SIZE * 2],
+            position: 0,
+            timer: wave_period(0),
+        }
+    }
+
+    /// Resets everything but wave RAM
+    pub fn reset(&mut self) {
+        *self = Wave {
+            ram: self.ram,
+            ..Wave::new()
+        };
+    }
+
+    /// SOUND3CNT_L
+    pub fn write_select(&mut self, value: u32) {
+        self.two_banks = value.bit_is_high(5);
+        self.bank = value.bit_is_high(6) as usize;
+        self.dac_enabled = value.bit_is_high(7);
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    /// SOUND3CNT_H
+    pub fn write_length_volume(&mut self, value: u32) {
+        self.length.load(value & 0xff);
+        self.volume = (value >> 13) & 0b11;
+        self.force_volume = value.bit_is_high(15);
+    }
+
+    /// SOUND3CNT_X
+    pub fn write_frequency(&mut self, value: u32) {
+        self.frequency = value & 0x7ff;
+        self.length.enabled = value.bit_is_high(14);
+        if value.bit_is_high(15) {
+            self.enabled = self.dac_enabled;
+            self.length.trigger();
+            self.position = 0;
+            self.timer = wave_period(self.frequency);
+        }
+    }
+
+    /// Writes a halfword of wave RAM, `offset` is from the start of WAVE_RAM
+    pub fn write_ram(&mut self, offset: usize, value: u32) {
+        let idx = (1 - self.bank) * BANK_SIZE + (offset & 0xe);
+        self.ram[idx] = value as u8;
+        self.ram[idx + 1] = (value >> 8) as u8;
+    }
+
+    /// The halfword of wave RAM the CPU sees at `offset`
+    pub fn read_ram(&self, offset: usize) -> u32 {
+        let idx = (1 - self.bank) * BANK_SIZE + (offset & 0xe);
+        self.ram[idx] as u32 | (self.ram[idx + 1] as u32) << 8
+    }
+
+    pub fn run(&mut self, cycles: u32) {
+        let steps = run_timer(&mut self.timer, wave_period(self.frequency), cycles);
+        let samples = if self.two_banks { BANK_SAMPLES * 2 } else { BANK_SAMPLES };
+        self.position = (self.position + steps) % samples;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// The current 4 bit sample
+    pub fn output(&self) -> u32 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let sample = (self.bank as u32 * BANK_SAMPLES + self.position) % (BANK_SAMPLES * 2);
+        let byte = self.ram[(sample / 2) as usize] as u32;
+        let sample = if sample.is_multiple_of(2) { byte >> 4 } else { byte & 0xf };
+        match (self.force_volume, self.volume) {
+            (true, _) => sample * 3 / 4,
+            (false, 0) => 0,
+            (false, volume) => sample >> (volume - 1),
+        }
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::Wave;
+
+    #[test]
+    fn wave_ram_banks() {
+        let mut wave = Wave::new();
+        // Playing bank 0, so the CPU writes bank 1
+        wave.write_select(0x80);
+        wave.write_ram(0, 0x00f0);
+        assert_eq!(0x00f0, wave.read_ram(0));
+        wave.write_length_volume(0x2000);
+        wave.write_frequency(0x87ff);
+        assert_eq!(0, wave.output());
+
+        // Switching to bank 1 plays what was written
+        wave.write_select(0xc0);
+        assert_eq!(15, wave.output());
+        wave.run(8);
+        assert_eq!(0, wave.output());
+        assert_eq!(0, wave.read_ram(0));
+    }
+
+    #[test]
+    fn wave_volume() {
+        let mut wave = Wave::new();
+        wave.write_select(0xc0);
+        wave.write_ram(0, 0x00c0);
+        wave.write_select(0x80);
+        wave.write_frequency(0x8000);
+
+        wave.write_length_volume(0x4000);
+        assert_eq!(6, wave.output());
+        wave.write_length_volume(0x6000);
+        assert_eq!(3, wave.output());
+        wave.write_length_volume(0x8000);
+        assert_eq!(9, wave.output());
+    }
+}
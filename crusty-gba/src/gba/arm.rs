@@ -849,6 +849,7 @@ impl Operation for SingleDataTfx {
             };
             cpu.set_register(self.rd, res);
             if self.rd== PC {
+                cpu.flush_pipeline(mem, (res & !0b11) as usize);
                 // NOTE: 2S + 2N + 1I
                 cpu.add_cycles(cycles + 4);
             } else {
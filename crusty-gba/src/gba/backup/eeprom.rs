@@ -0,0 +1,165 @@
+// EEPROM is addressed in blocks of 64 bits
+const BLOCK_SIZE: usize = 8;
+const BLOCK_BITS: usize = 64;
+const SMALL_SIZE: usize = 0x200;
+const LARGE_SIZE: usize = 0x2000;
+// A read sends 4 bits of junk before the block
+const READ_JUNK_BITS: u32 = 4;
+
+/// EEPROM, which is accessed one bit at a time through DMA3. Requests start with 0b11 for a
+/// read or 0b10 for a write, followed by the block's address, the data for a write and a 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Eeprom {
+    pub data: Vec<u8>,
+    /// 6 bits for 512 bytes and 14 bits for 8 KiB, unknown until the first transfer
+    address_bits: Option<u32>,
+    /// The bits of the request being sent
+    request: Vec<bool>,
+    /// The block being read out, and how many bits are left in it
+    read_block: u64,
+    read_bits: u32,
+}
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Eeprom {
+            data: vec![0xff; LARGE_SIZE],
+            address_bits: None,
+            request: Vec::new(),
+            read_block: 0,
+            read_bits: 0,
+        }
+    }
+}
+
+impl Eeprom {
+    /// Loads a .sav file, whose size also decides the address width
+    pub fn load(&mut self, save: &[u8]) {
+        if save.len() == SMALL_SIZE {
+            self.set_address_bits(6);
+        }
+        let len = self.data.len().min(save.len());
+        self.data[..len].copy_from_slice(&save[..len]);
+    }
+
+    fn set_address_bits(&mut self, bits: u32) {
+        self.address_bits = Some(bits);
+        self.data.resize(if bits == 6 { SMALL_SIZE } else { LARGE_SIZE }, 0xff);
+    }
+
+    /// The address width is picked from the length of the first DMA to the EEPROM, read
+    /// requests are 9 or 17 bits and write requests are 73 or 81 bits
+    pub fn start_transfer(&mut self, units: u32) {
+        if self.address_bits.is_some() {
+            return;
+        }
+        match units {
+            9 | 73 => self.set_address_bits(6),
+            17 | 81 => self.set_address_bits(14),
+            _ => {}
+        }
+    }
+
+    /// The next bit of a block being read out. Otherwise it's the ready bit, which is always
+    /// set since writes finish right away
+    pub fn read_bit(&mut self) -> u32 {
+        if self.read_bits == 0 {
+            return 1;
+        }
+        self.read_bits -= 1;
+        if self.read_bits >= BLOCK_BITS as u32 {
+            return 0;
+        }
+        (self.read_block >> self.read_bits) as u32 & 1
+    }
+
+    /// Takes the next bit of a request, returns true when a block has been written
+    pub fn write_bit(&mut self, value: u32) -> bool {
+        // NOTE: Requests always start with 1, so a leading 0 is dropped to get back in sync
+        if self.request.is_empty() && value & 1 == 0 {
+            return false;
+        }
+        self.request.push(value & 1 == 1);
+        let address_bits = self.address_bits.unwrap_or(14) as usize;
+        let is_read = self.request.get(1) == Some(&true);
+        let request_bits = if is_read {
+            2 + address_bits + 1
+        } else {
+            2 + address_bits + BLOCK_BITS + 1
+        };
+        if self.request.len() < request_bits {
+            return false;
+        }
+
+        let request = std::mem::take(&mut self.request);
+        let bits_to_u64 = |bits: &[bool]| bits.iter().fold(0, |acc, b| (acc << 1) | *b as u64);
+        let blocks = self.data.len() / BLOCK_SIZE;
+        let block = bits_to_u64(&request[2..2 + address_bits]) as usize % blocks * BLOCK_SIZE;
+        if is_read {
+            let bytes = self.data[block..block + BLOCK_SIZE].try_into().unwrap();
+            self.read_block = u64::from_be_bytes(bytes);
+            self.read_bits = READ_JUNK_BITS + BLOCK_BITS as u32;
+            return false;
+        }
+        let value = bits_to_u64(&request[2 + address_bits..2 + address_bits + BLOCK_BITS]);
+        self.data[block..block + BLOCK_SIZE].copy_from_slice(&value.to_be_bytes());
+        true
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::Eeprom;
+
+    fn send(eeprom: &mut Eeprom, value: u64, bits: u32) -> bool {
+        (0..bits).rev().map(|i| eeprom.write_bit((value >> i) as u32)).last().unwrap()
+    }
+
+    #[test]
+    fn eeprom_detects_address_width() {
+        let mut eeprom = Eeprom::default();
+        eeprom.start_transfer(9);
+        assert_eq!(0x200, eeprom.data.len());
+        // Only the first transfer counts
+        eeprom.start_transfer(17);
+        assert_eq!(0x200, eeprom.data.len());
+
+        let mut eeprom = Eeprom::default();
+        eeprom.load(&[0; 0x200]);
+        assert_eq!(Some(6), eeprom.address_bits);
+    }
+
+    #[test]
+    fn eeprom_writes_and_reads_blocks() {
+        let mut eeprom = Eeprom::default();
+        eeprom.start_transfer(81);
+        // Write to block 3
+        assert!(!send(&mut eeprom, 0b10, 2));
+        assert!(!send(&mut eeprom, 3, 14));
+        assert!(!send(&mut eeprom, 0x0123456789abcdef, 64));
+        assert!(send(&mut eeprom, 0, 1));
+        assert_eq!(&[0x01, 0x23, 0xef], &[eeprom.data[24], eeprom.data[25], eeprom.data[31]]);
+        assert_eq!(1, eeprom.read_bit());
+
+        // Read block 3 back, after 4 junk bits
+        send(&mut eeprom, (0b11 << 15) | (3 << 1), 17);
+        let bits: Vec<u32> = (0..68).map(|_| eeprom.read_bit()).collect();
+        let block = bits[4..].iter().fold(0u64, |acc, b| (acc << 1) | *b as u64);
+        assert_eq!([0, 0, 0, 0], bits[..4]);
+        assert_eq!(0x0123456789abcdef, block);
+        assert_eq!(1, eeprom.read_bit());
+    }
+
+    #[test]
+    fn eeprom_drops_leading_zeros() {
+        let mut eeprom = Eeprom::default();
+        eeprom.start_transfer(9);
+        // A stray 0 before the write request
+        assert!(!send(&mut eeprom, 0, 1));
+        assert!(!send(&mut eeprom, 0b10_000001, 8));
+        assert!(!send(&mut eeprom, 0x0123456789abcdef, 64));
+        assert!(send(&mut eeprom, 0, 1));
+        assert_eq!(&[0x01, 0xef], &[eeprom.data[8], eeprom.data[15]]);
+        assert!(eeprom.request.is_empty());
+    }
+}
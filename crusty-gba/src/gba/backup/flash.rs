@@ -0,0 +1,221 @@
+// Flash is switched in 64 KiB banks, the 128 KiB chips have 2 of them
+const BANK_SIZE: usize = 0x10000;
+const SECTOR_SIZE: usize = 0x1000;
+// Commands are unlocked by writing 0xaa to 0x5555 and then 0x55 to 0x2aaa
+const UNLOCK_ADDRESS_1: usize = 0x5555;
+const UNLOCK_ADDRESS_2: usize = 0x2aaa;
+// Atmel chips program a whole page of 128 bytes at once
+const ATMEL_PAGE_SIZE: u32 = 128;
+
+/// The Flash chips used in game paks, each one reports its own manufacturer and device ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FlashChip {
+    /// 64 KiB
+    Panasonic,
+    /// 64 KiB, programmed a page at a time
+    Atmel,
+    /// 64 KiB
+    Macronix64,
+    /// 128 KiB
+    Macronix128,
+    /// 128 KiB
+    Sanyo,
+}
+
+impl FlashChip {
+    /// The manufacturer ID followed by the device ID
+    fn id(&self) -> [u8; 2] {
+        match self {
+            FlashChip::Panasonic => [0x32, 0x1b],
+            FlashChip::Atmel => [0x1f, 0x3d],
+            FlashChip::Macronix64 => [0xc2, 0x1c],
+            FlashChip::Macronix128 => [0xc2, 0x09],
+            FlashChip::Sanyo => [0x62, 0x13],
+        }
+    }
+
+    fn banks(&self) -> usize {
+        match self {
+            FlashChip::Macronix128 | FlashChip::Sanyo => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Flash memory, which is read like SRAM but written through a sequence of commands
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flash {
+    chip: FlashChip,
+    pub data: Vec<u8>,
+    bank: usize,
+    /// How many of the unlock writes have been made
+    unlock: u32,
+    /// Reads from the first two bytes return the chip's ID
+    id_mode: bool,
+    /// The next command erases
+    erase: bool,
+    /// The next write selects the bank
+    bank_select: bool,
+    /// How many bytes can still be programmed
+    program: u32,
+}
+
+impl Flash {
+    pub fn new(chip: FlashChip) -> Self {
+        Flash {
+            chip,
+            // Erased Flash reads as all 1s
+            data: vec![0xff; BANK_SIZE * chip.banks()],
+            bank: 0,
+            unlock: 0,
+            id_mode: false,
+            erase: false,
+            bank_select: false,
+            program: 0,
+        }
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        let offset = address & (BANK_SIZE - 1);
+        if self.id_mode && offset < 2 {
+            return self.chip.id()[offset];
+        }
+        self.data[self.bank * BANK_SIZE + offset]
+    }
+
+    /// Returns true when a program or erase changed the data, commands don't count
+    pub fn write(&mut self, address: usize, value: u8) -> bool {
+        let offset = address & (BANK_SIZE - 1);
+        if self.program > 0 {
+            self.program -= 1;
+            let byte = &mut self.data[self.bank * BANK_SIZE + offset];
+            let changed = *byte != value;
+            *byte = value;
+            return changed;
+        }
+        if self.bank_select {
+            self.bank_select = false;
+            if offset == 0 {
+                self.bank = value as usize % self.chip.banks();
+            }
+            return false;
+        }
+
+        match (self.unlock, offset, value) {
+            (0, UNLOCK_ADDRESS_1, 0xaa) => self.unlock = 1,
+            (1, UNLOCK_ADDRESS_2, 0x55) => self.unlock = 2,
+            (2, _, _) => {
+                self.unlock = 0;
+                return self.command(offset, value);
+            }
+            // NOTE: Exiting ID mode also works without the unlock sequence on some chips
+            (_, _, 0xf0) => {
+                self.unlock = 0;
+                self.id_mode = false;
+            }
+            _ => self.unlock = 0,
+        }
+        false
+    }
+
+    /// Returns true when an erase changed the data
+    fn command(&mut self, offset: usize, command: u8) -> bool {
+        let erase = std::mem::take(&mut self.erase);
+        match command {
+            0x90 => self.id_mode = true,
+            0xf0 => self.id_mode = false,
+            0x80 => self.erase = true,
+            0x10 if erase && offset == UNLOCK_ADDRESS_1 => return erase_bytes(&mut self.data),
+            0x30 if erase => {
+                let sector = self.bank * BANK_SIZE + (offset & !(SECTOR_SIZE - 1));
+                return erase_bytes(&mut self.data[sector..sector + SECTOR_SIZE]);
+            }
+            0xa0 if self.chip == FlashChip::Atmel => self.program = ATMEL_PAGE_SIZE,
+            0xa0 => self.program = 1,
+            0xb0 if self.chip.banks() > 1 => self.bank_select = true,
+            _ => {}
+        }
+        false
+    }
+}
+
+/// Sets `bytes` back to the erased 1s, returns true when any of them changed
+fn erase_bytes(bytes: &mut [u8]) -> bool {
+    let changed = bytes.iter().any(|b| *b != 0xff);
+    bytes.fill(0xff);
+    changed
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{Flash, FlashChip};
+
+    fn command(flash: &mut Flash, command: u8) {
+        flash.write(0x5555, 0xaa);
+        flash.write(0x2aaa, 0x55);
+        flash.write(0x5555, command);
+    }
+
+    #[test]
+    fn flash_reads_id() {
+        let mut flash = Flash::new(FlashChip::Sanyo);
+        assert_eq!(0xff, flash.read(0));
+        command(&mut flash, 0x90);
+        assert_eq!([0x62, 0x13], [flash.read(0), flash.read(1)]);
+        command(&mut flash, 0xf0);
+        assert_eq!(0xff, flash.read(0));
+    }
+
+    #[test]
+    fn flash_programs_and_erases() {
+        let mut flash = Flash::new(FlashChip::Panasonic);
+        // Writes without a command are ignored
+        flash.write(0x1234, 0x12);
+        assert_eq!(0xff, flash.read(0x1234));
+
+        command(&mut flash, 0xa0);
+        assert!(flash.write(0x1234, 0x12));
+        assert!(!flash.write(0x1235, 0x34));
+        assert_eq!([0x12, 0xff], [flash.read(0x1234), flash.read(0x1235)]);
+        command(&mut flash, 0xa0);
+        flash.write(0x2000, 0x56);
+
+        // Sector erase only clears the 4 KiB sector that's written
+        command(&mut flash, 0x80);
+        flash.write(0x5555, 0xaa);
+        flash.write(0x2aaa, 0x55);
+        flash.write(0x1000, 0x30);
+        assert_eq!([0xff, 0x56], [flash.read(0x1234), flash.read(0x2000)]);
+
+        command(&mut flash, 0x80);
+        flash.write(0x5555, 0xaa);
+        flash.write(0x2aaa, 0x55);
+        assert!(flash.write(0x5555, 0x10));
+        assert_eq!(0xff, flash.read(0x2000));
+    }
+
+    #[test]
+    fn flash_switches_banks() {
+        let mut flash = Flash::new(FlashChip::Macronix128);
+        command(&mut flash, 0xb0);
+        flash.write(0, 1);
+        command(&mut flash, 0xa0);
+        flash.write(0x10, 0x42);
+        assert_eq!(0x42, flash.data[0x10010]);
+
+        command(&mut flash, 0xb0);
+        flash.write(0, 0);
+        assert_eq!(0xff, flash.read(0x10));
+    }
+
+    #[test]
+    fn flash_atmel_programs_pages() {
+        let mut flash = Flash::new(FlashChip::Atmel);
+        command(&mut flash, 0xa0);
+        for i in 0..128 {
+            flash.write(0x80 + i, i as u8);
+        }
+        flash.write(0x100, 0x12);
+        assert_eq!([0, 127, 0xff], [flash.read(0x80), flash.read(0xff), flash.read(0x100)]);
+    }
+}
@@ -0,0 +1,160 @@
+mod eeprom;
+mod flash;
+mod sram;
+
+use eeprom::Eeprom;
+use flash::Flash;
+pub use flash::FlashChip;
+use sram::Sram;
+
+// The ID strings the SDK puts in the ROM for each kind of save chip
+const SRAM_ID: &[u8] = b"SRAM_V";
+const FLASH_64_IDS: [&[u8]; 2] = [b"FLASH_V", b"FLASH512_V"];
+const FLASH_128_ID: &[u8] = b"FLASH1M_V";
+const EEPROM_ID: &[u8] = b"EEPROM_V";
+
+/// The save chip in the game pak, mapped at 0xe000000 or 0xd000000 for EEPROM
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Backup {
+    None,
+    Sram(Sram),
+    Flash(Flash),
+    Eeprom(Eeprom),
+}
+
+impl Backup {
+    /// Picks the chip from the ID string in the ROM
+    pub fn detect(rom: &[u32]) -> Self {
+        let bytes: Vec<u8> = rom.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let has_id = |id: &[u8]| bytes.windows(id.len()).any(|w| w == id);
+        if has_id(SRAM_ID) {
+            return Backup::Sram(Sram::default());
+        }
+        // NOTE: The chips with the most common IDs are used for each size
+        if FLASH_64_IDS.iter().any(|id| has_id(id)) {
+            return Backup::Flash(Flash::new(FlashChip::Panasonic));
+        }
+        if has_id(FLASH_128_ID) {
+            return Backup::Flash(Flash::new(FlashChip::Sanyo));
+        }
+        if has_id(EEPROM_ID) {
+            return Backup::Eeprom(Eeprom::default());
+        }
+        // NOTE: Games without an ID string get SRAM, since it's the simplest to use
+        Backup::Sram(Sram::default())
+    }
+
+    /// Swaps a detected Flash chip for `chip`, for games that check the ID
+    pub fn set_flash_chip(&mut self, chip: FlashChip) {
+        if let Backup::Flash(_) = self {
+            *self = Backup::Flash(Flash::new(chip));
+        }
+    }
+
+    /// A byte from the 8 bit backup bus, nothing connected reads as all 1s
+    pub fn read(&self, address: usize) -> u8 {
+        match self {
+            Backup::None | Backup::Eeprom(_) => 0xff,
+            Backup::Sram(sram) => sram.read(address),
+            Backup::Flash(flash) => flash.read(address),
+        }
+    }
+
+    /// Writes a byte to the 8 bit backup bus, returns true when the chip's data changed
+    pub fn write(&mut self, address: usize, value: u8) -> bool {
+        match self {
+            Backup::None | Backup::Eeprom(_) => false,
+            Backup::Sram(sram) => sram.write(address, value),
+            Backup::Flash(flash) => flash.write(address, value),
+        }
+    }
+
+    pub fn is_eeprom(&self) -> bool {
+        matches!(self, Backup::Eeprom(_))
+    }
+
+    /// Called before each DMA to the EEPROM, with the number of units it transfers
+    pub fn start_eeprom_transfer(&mut self, units: u32) {
+        if let Backup::Eeprom(eeprom) = self {
+            eeprom.start_transfer(units);
+        }
+    }
+
+    /// The next bit from the EEPROM, in bit 0 of the halfword
+    pub fn read_eeprom(&mut self) -> u32 {
+        match self {
+            Backup::Eeprom(eeprom) => eeprom.read_bit(),
+            _ => 1,
+        }
+    }
+
+    /// Sends bit 0 of `value` to the EEPROM, returns true when a block has been written
+    pub fn write_eeprom(&mut self, value: u32) -> bool {
+        match self {
+            Backup::Eeprom(eeprom) => eeprom.write_bit(value),
+            _ => false,
+        }
+    }
+
+    /// The contents of the chip, as stored in a .sav file
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Backup::None => &[],
+            Backup::Sram(sram) => &sram.data,
+            Backup::Flash(flash) => &flash.data,
+            Backup::Eeprom(eeprom) => &eeprom.data,
+        }
+    }
+
+    /// Loads the contents of a .sav file, anything past the size of the chip is ignored
+    pub fn load(&mut self, save: &[u8]) {
+        let data = match self {
+            Backup::None => return,
+            Backup::Eeprom(eeprom) => return eeprom.load(save),
+            Backup::Sram(sram) => &mut sram.data,
+            Backup::Flash(flash) => &mut flash.data,
+        };
+        let len = data.len().min(save.len());
+        data[..len].copy_from_slice(&save[..len]);
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::Backup;
+
+    #[test]
+    fn backup_detects_sram() {
+        // "SRAM_V113"
+        let rom = vec![0, 0x4d415253, 0x3131565f, 0x33];
+        let mut backup = Backup::detect(&rom);
+        assert!(matches!(backup, Backup::Sram(_)));
+
+        backup.load(&[1, 2, 3]);
+        assert_eq!(&[1, 2, 3, 0xff], &backup.data()[..4]);
+    }
+
+    #[test]
+    fn backup_detects_flash() {
+        // "FLASH1M_V103"
+        let rom = vec![0x53414c46, 0x5f4d3148, 0x33303156];
+        let backup = Backup::detect(&rom);
+        assert!(matches!(backup, Backup::Flash(_)));
+        assert_eq!(0x20000, backup.data().len());
+
+        // "FLASH512_V13"
+        let rom = vec![0x53414c46, 0x32313548, 0x3331565f];
+        assert_eq!(0x10000, Backup::detect(&rom).data().len());
+    }
+
+    #[test]
+    fn backup_detects_eeprom() {
+        // "EEPROM_V124"
+        let rom = vec![0x52504545, 0x565f4d4f, 0x343231];
+        let mut backup = Backup::detect(&rom);
+        assert!(backup.is_eeprom());
+
+        backup.load(&[0; 0x200]);
+        assert_eq!(0x200, backup.data().len());
+    }
+}
@@ -0,0 +1,46 @@
+// 32 KiB, mirrored through the rest of the backup region
+const SRAM_SIZE: usize = 0x8000;
+
+/// Battery backed SRAM, which is only wired to the lower 8 bits of the data bus
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sram {
+    pub data: Vec<u8>,
+}
+
+impl Default for Sram {
+    fn default() -> Self {
+        // Unwritten SRAM reads as all 1s
+        Sram {
+            data: vec![0xff; SRAM_SIZE],
+        }
+    }
+}
+
+impl Sram {
+    pub fn read(&self, address: usize) -> u8 {
+        self.data[address & (SRAM_SIZE - 1)]
+    }
+
+    /// Returns true when the byte changed
+    pub fn write(&mut self, address: usize, value: u8) -> bool {
+        let byte = &mut self.data[address & (SRAM_SIZE - 1)];
+        let changed = *byte != value;
+        *byte = value;
+        changed
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::Sram;
+
+    #[test]
+    fn sram_mirrors() {
+        let mut sram = Sram::default();
+        assert_eq!(0xff, sram.read(0x0e000000));
+        assert!(sram.write(0x0e000010, 0x12));
+        assert!(!sram.write(0x0e000010, 0x12));
+        assert_eq!(0x12, sram.read(0x0e008010));
+        assert_eq!(0x12, sram.read(0x0f000010));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::gba::error::InstructionDecodeError;
 use crate::gba::thumb::Thumb;
-use crate::gba::{CPSR_FIQ, CPSR_IRQ, Operation};
+use crate::gba::{CPSR_FIQ, CPSR_IRQ, EXCEPTION_VECTOR_IRQ, Operation};
 use crate::memory::Memory;
 
 use super::arm::Arm;
@@ -494,6 +494,18 @@ impl Cpu {
     }
 
     pub fn tick(&mut self, ram: &mut impl Memory) {
+        // Nothing runs until an interrupt wakes the CPU back up
+        if ram.is_halted() {
+            self.add_cycles(1);
+            return;
+        }
+
+        // Interrupts are checked between instructions
+        if !self.is_irq() && ram.interrupt_pending() {
+            self.enter_irq(ram);
+            return;
+        }
+
         // NOTE: Refactor now that PC updates after an instruction runs
         let inst = self.decode;
         let next_inst = if self.is_thumb_mode() {
@@ -528,6 +540,23 @@ impl Cpu {
         );
     }
 
+    fn enter_irq(&mut self, ram: &mut impl Memory) {
+        // The instruction in decode hasn't run yet, so the handler needs to return to it.
+        // Handlers return with `subs pc, lr, #4` for both ARM and Thumb
+        let addr_to_return_to = self.instruction_address().wrapping_add(4) as u32;
+        debug!("Entering IRQ, returning to {:#010x}", addr_to_return_to);
+        self.set_register_for_mode(LR, addr_to_return_to, CpuMode::Irq);
+        self.set_psr_for_mode(self.cpsr, CpuMode::Irq);
+
+        self.update_thumb(false);
+        self.set_cpsr_mode(CpuMode::Irq);
+        self.disable_irq();
+        self.flush_pipeline(ram, EXCEPTION_VECTOR_IRQ);
+        self.registers[PC] = self.registers[PC].wrapping_add(4);
+        // NOTE: 2S + 1N
+        self.add_cycles(3);
+    }
+
     fn run_instruction(&mut self, ram: &mut impl Memory, inst: u32, i_addr: usize) {
         let op = if !self.is_thumb_mode() {
             let cond = Conditional::from(inst);
@@ -558,15 +587,31 @@ impl Cpu {
         let old_cycles = self.cycles;
         while self.cycles - old_cycles < num_of_cycles {
             self.tick(ram);
+            self.run_dma(ram);
+            ram.tick(self.cycles);
+        }
+    }
+
+    /// Runs any active DMA transfers. The CPU is halted while DMA is running
+    /// so the cycles are just added on top
+    pub fn run_dma(&mut self, ram: &mut SystemMemory) {
+        if !ram.is_dma_enabled() {
+            return;
+        }
+
+        match ram.run_dma() {
+            Ok(cycles) => self.add_cycles(cycles),
+            Err(e) => error!("{}", e),
         }
     }
 }
 
 mod test {
     #![allow(unused)]
-    use super::{Cpu, PC, SP};
+    use super::{Cpu, CpuMode, LR, PC, SP};
     use crate::SystemMemory;
     use crate::memory::Memory;
+    use crate::utils::io_registers::{INTERRUPT_ENABLE, INTERRUPT_MASTER_ENABLE, INTERRUPT_REQUEST};
 
     #[test]
     fn run_add_instruction() {
@@ -768,6 +813,27 @@ mod test {
         assert_eq!(cpu.cycles, 7);
     }
 
+    #[test]
+    fn run_ldr_into_pc() {
+        let mut ram = SystemMemory::test();
+        let mut cpu = Cpu {
+            registers: [0; 16],
+            ..Cpu::default()
+        };
+        cpu.registers[0] = 0x10;
+        // An unaligned address is word aligned
+        let _ = ram.write_word(0x10, 0x22);
+        let _ = ram.write_word(0x20, 0xe3a01001);
+        let _ = ram.write_word(0x24, 0xe3a02002);
+
+        // ldr pc, [r0]
+        cpu.run_instruction(&mut ram, 0xe590f000, 0x0);
+
+        assert_eq!(cpu.registers[PC], 0x24);
+        assert_eq!(cpu.decode, 0xe3a01001);
+        assert_eq!(cpu.fetch, 0xe3a02002);
+    }
+
     #[test]
     fn check_cycles_thumb_ldrh() {
         let mut ram = SystemMemory::test_pak_ram();
@@ -1381,4 +1447,84 @@ mod test {
         assert!(!cpu.c_status());
         assert!(cpu.v_status());
     }
+
+    #[test]
+    fn run_irq_entry() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(INTERRUPT_ENABLE, 0x1).unwrap();
+        ram.write_halfword(INTERRUPT_MASTER_ENABLE, 0x1).unwrap();
+        let mut cpu = Cpu::default();
+        cpu.reset_cpu();
+        cpu.update_thumb(true);
+        cpu.registers[PC] = 0x8000104;
+
+        // Nothing requested yet
+        assert!(!ram.interrupt_pending());
+        ram.request_interrupt(0x1);
+        assert!(ram.interrupt_pending());
+
+        let old_cpsr = cpu.cpsr;
+        cpu.tick(&mut ram);
+
+        assert_eq!(CpuMode::Irq, cpu.get_mode());
+        assert!(cpu.is_irq());
+        assert!(!cpu.is_thumb_mode());
+        assert_eq!(0x8000104, cpu.get_register(LR));
+        assert_eq!(old_cpsr, cpu.get_psr());
+        assert_eq!(0x20, cpu.pc());
+        assert_eq!(0xe92d500f, cpu.decode);
+
+        // Acknowledging the interrupt
+        ram.write_halfword(INTERRUPT_REQUEST, 0x1).unwrap();
+        assert!(!ram.interrupt_pending());
+    }
+
+    #[test]
+    fn run_irq_blocked_by_cpsr() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(INTERRUPT_ENABLE, 0x1).unwrap();
+        ram.write_halfword(INTERRUPT_MASTER_ENABLE, 0x1).unwrap();
+        ram.request_interrupt(0x1);
+        let mut cpu = Cpu::default();
+        cpu.reset_cpu();
+        cpu.disable_irq();
+
+        cpu.tick(&mut ram);
+        assert_eq!(CpuMode::System, cpu.get_mode());
+    }
+
+    #[test]
+    fn run_irq_handler_and_return() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(INTERRUPT_ENABLE, 0x1).unwrap();
+        ram.write_halfword(INTERRUPT_MASTER_ENABLE, 0x1).unwrap();
+        // User handler acknowledges the interrupt and returns
+        // mov r0, #0x4000000; add r0, r0, #0x200; mov r1, #1; strh r1, [r0, #0x2]; bx lr
+        ram.write_word(0x3000000, 0xe3a00301).unwrap();
+        ram.write_word(0x3000004, 0xe2800c02).unwrap();
+        ram.write_word(0x3000008, 0xe3a01001).unwrap();
+        ram.write_word(0x300000c, 0xe1c010b2).unwrap();
+        ram.write_word(0x3000010, 0xe12fff1e).unwrap();
+        ram.write_word(0x3007ffc, 0x3000000).unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.reset_cpu();
+        cpu.registers[PC] = 0x3001008;
+        cpu.registers[0] = 0xaa;
+        ram.request_interrupt(0x1);
+
+        // Entering the IRQ, the stub, the handler and then back again
+        cpu.tick(&mut ram);
+        let mut n = 0;
+        while cpu.instruction_address() != 0x3001000 && n < 100 {
+            cpu.tick(&mut ram);
+            n += 1;
+        }
+
+        assert_eq!(0x3001000, cpu.instruction_address());
+        assert_eq!(CpuMode::System, cpu.get_mode());
+        assert!(!cpu.is_irq());
+        assert_eq!(0xaa, cpu.get_register(0));
+        assert!(!ram.interrupt_pending());
+    }
 }
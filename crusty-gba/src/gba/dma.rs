@@ -1,4 +1,48 @@
 use crate::utils::Bitable;
+use crate::utils::io_registers::DMA_0_SAD;
+
+pub(super) const DMA_CHANNELS: usize = 4;
+// Each channel has SAD, DAD and CNT_L/CNT_H, 12 bytes in total
+const DMA_CHANNEL_OFFSET: usize = 0xc;
+
+pub(super) const DMA_START_IMMEDIATE: u32 = 0;
+pub(super) const DMA_START_V_BLANK: u32 = 1;
+pub(super) const DMA_START_H_BLANK: u32 = 2;
+pub(super) const DMA_START_SPECIAL: u32 = 3;
+
+pub(super) const DMA_ADDRESS_INCREMENT: u32 = 0;
+pub(super) const DMA_ADDRESS_DECREMENT: u32 = 1;
+pub(super) const DMA_ADDRESS_FIXED: u32 = 2;
+pub(super) const DMA_ADDRESS_INCREMENT_RELOAD: u32 = 3;
+
+// Sound FIFO transfers ignore the word count and always send 4 words
+pub(super) const DMA_SOUND_FIFO_COUNT: u32 = 4;
+
+pub(super) fn dma_source_address(channel: usize) -> usize {
+    DMA_0_SAD + channel * DMA_CHANNEL_OFFSET
+}
+
+pub(super) fn dma_destination_address(channel: usize) -> usize {
+    dma_source_address(channel) + 0x4
+}
+
+pub(super) fn dma_count_address(channel: usize) -> usize {
+    dma_source_address(channel) + 0x8
+}
+
+pub(super) fn dma_control_address(channel: usize) -> usize {
+    dma_source_address(channel) + 0xa
+}
+
+/// Events that can start a DMA that isn't set to start immediately
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DmaTrigger {
+    VBlank,
+    HBlank,
+    /// Holds the address of the FIFO that needs to be refilled
+    SoundFifo(usize),
+    VideoCapture,
+}
 
 pub(super) struct DmaControl {
     pub destination_control: u32,
@@ -25,3 +69,98 @@ impl From<u32> for DmaControl {
         }
     }
 }
+
+impl DmaControl {
+    /// Size in bytes of a single unit, dma_tfx_type is set for 32 bit transfers
+    pub fn unit_size(&self) -> u32 {
+        if self.dma_tfx_type {
+            4
+        } else {
+            2
+        }
+    }
+
+    /// DMA1 and DMA2 in special mode refill the sound FIFOs
+    pub fn is_sound_fifo(&self, channel: usize) -> bool {
+        self.dma_start_timing == DMA_START_SPECIAL && (channel == 1 || channel == 2)
+    }
+
+    pub fn is_triggered_by(&self, channel: usize, trigger: DmaTrigger) -> bool {
+        match trigger {
+            DmaTrigger::VBlank => self.dma_start_timing == DMA_START_V_BLANK,
+            DmaTrigger::HBlank => self.dma_start_timing == DMA_START_H_BLANK,
+            DmaTrigger::SoundFifo(_) => self.is_sound_fifo(channel),
+            DmaTrigger::VideoCapture => self.dma_start_timing == DMA_START_SPECIAL && channel == 3,
+        }
+    }
+}
+
+/// The internal registers of a DMA channel. SAD, DAD and CNT_L are latched into
+/// these when the channel is enabled, so the transfer never reads them back.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct DmaChannel {
+    pub source: u32,
+    pub destination: u32,
+    pub count: u32,
+    pub running: bool,
+    /// Set when the start timing event has happened, and the transfer is waiting to run
+    pub pending: bool,
+}
+
+impl DmaChannel {
+    pub fn latch(&mut self, channel: usize, source: u32, destination: u32, count: u32) {
+        self.source = source & source_mask(channel);
+        self.destination = destination & destination_mask(channel);
+        self.count = word_count(channel, count);
+        self.running = true;
+        self.pending = false;
+    }
+
+    pub fn reload_count(&mut self, channel: usize, count: u32) {
+        self.count = word_count(channel, count);
+    }
+
+    pub fn reload_destination(&mut self, channel: usize, destination: u32) {
+        self.destination = destination & destination_mask(channel);
+    }
+}
+
+// DMA0 can only read from internal memory, and only DMA3 can write to the game pak
+fn source_mask(channel: usize) -> u32 {
+    if channel == 0 {
+        0x7ffffff
+    } else {
+        0xfffffff
+    }
+}
+
+fn destination_mask(channel: usize) -> u32 {
+    if channel == 3 {
+        0xfffffff
+    } else {
+        0x7ffffff
+    }
+}
+
+// A count of 0 is treated as the max count for the channel
+fn word_count(channel: usize, count: u32) -> u32 {
+    let (mask, max) = if channel == 3 {
+        (0xffff, 0x10000)
+    } else {
+        (0x3fff, 0x4000)
+    };
+
+    match count & mask {
+        0 => max,
+        c => c,
+    }
+}
+
+pub(super) fn next_address(address: u32, control: u32, unit_size: u32) -> u32 {
+    match control {
+        // NOTE: Increment/Reload is prohibited for the source, treating it as increment
+        DMA_ADDRESS_INCREMENT | DMA_ADDRESS_INCREMENT_RELOAD => address.wrapping_add(unit_size),
+        DMA_ADDRESS_DECREMENT => address.wrapping_sub(unit_size),
+        _ => address,
+    }
+}
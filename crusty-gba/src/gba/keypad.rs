@@ -0,0 +1,110 @@
+use crate::utils::Bitable;
+
+// KEYINPUT reads back as all 1s when nothing is pressed
+pub(super) const KEYS_RELEASED: u32 = 0x3ff;
+
+/// The buttons on the GBA, in the order of their bits in KEYINPUT and KEYCNT
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, strum_macros::Display, strum_macros::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Right,
+    Left,
+    Up,
+    Down,
+    R,
+    L,
+}
+
+impl Button {
+    pub fn bit(&self) -> u32 {
+        1 << (*self as u32)
+    }
+}
+
+pub(super) struct KeypadControl {
+    pub buttons: u32,
+    pub irq: bool,
+    /// Set when all of the buttons need to be pressed, otherwise any of them will do
+    pub and_condition: bool,
+}
+
+impl From<u32> for KeypadControl {
+    fn from(value: u32) -> Self {
+        KeypadControl {
+            buttons: value & KEYS_RELEASED,
+            irq: value.bit_is_high(14),
+            and_condition: value.bit_is_high(15),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct Keypad {
+    // Active high, unlike KEYINPUT
+    pressed: u32,
+}
+
+impl Keypad {
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.pressed |= button.bit();
+        } else {
+            self.pressed &= !button.bit();
+        }
+    }
+
+    /// The value of KEYINPUT, where a pressed button is 0
+    pub fn key_input(&self) -> u32 {
+        !self.pressed & KEYS_RELEASED
+    }
+
+    pub fn irq_condition(&self, control: &KeypadControl) -> bool {
+        if !control.irq || control.buttons == 0 {
+            return false;
+        }
+
+        let pressed = self.pressed & control.buttons;
+        if control.and_condition {
+            pressed == control.buttons
+        } else {
+            pressed != 0
+        }
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{Button, Keypad, KeypadControl};
+
+    #[test]
+    fn keypad_key_input_active_low() {
+        let mut keypad = Keypad::default();
+        assert_eq!(0x3ff, keypad.key_input());
+
+        keypad.set_button(Button::A, true);
+        keypad.set_button(Button::L, true);
+        assert_eq!(0x1fe, keypad.key_input());
+
+        keypad.set_button(Button::A, false);
+        assert_eq!(0x1ff, keypad.key_input());
+    }
+
+    #[test]
+    fn keypad_irq_conditions() {
+        let mut keypad = Keypad::default();
+        let or_control = KeypadControl::from(0x4000 | Button::A.bit() | Button::Start.bit());
+        let and_control = KeypadControl::from(0xc000 | Button::A.bit() | Button::Start.bit());
+
+        keypad.set_button(Button::Start, true);
+        assert!(keypad.irq_condition(&or_control));
+        assert!(!keypad.irq_condition(&and_control));
+
+        keypad.set_button(Button::A, true);
+        assert!(keypad.irq_condition(&and_control));
+        assert!(!keypad.irq_condition(&KeypadControl::from(Button::A.bit())));
+    }
+}
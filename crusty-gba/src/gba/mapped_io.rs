@@ -1,12 +1,142 @@
-use crate::utils::io_registers::{INTERRUPT_ENABLE, INTERRUPT_MASTER_ENABLE, INTERRUPT_REQUEST};
+use crate::utils::io_registers::{
+    BG2_POINT_X, BG2_POINT_Y, BG3_POINT_X, BG3_POINT_Y, DISP_CONTROL, DISP_STAT, DMA_0_CNT_H, DMA_1_CNT_H, DMA_2_CNT_H, DMA_3_CNT_H, FIFO_A, FIFO_B, HALT_CNT,
+    INTERRUPT_ENABLE, INTERRUPT_MASTER_ENABLE, INTERRUPT_REQUEST, KEY_CNT, KEY_INPUT,
+    SOUND_1_CNT_H, SOUND_1_CNT_L, SOUND_1_CNT_X, SOUND_2_CNT_H, SOUND_2_CNT_L, SOUND_3_CNT_H,
+    SOUND_3_CNT_L, SOUND_3_CNT_X, SOUND_4_CNT_H, SOUND_4_CNT_L, SOUND_BIAS, SOUND_CNT_H, SOUND_CNT_L, SOUND_CNT_X, TIMER_0_CNT_H, TIMER_0_CNT_L, TIMER_1_CNT_H, TIMER_1_CNT_L, TIMER_2_CNT_H,
+    TIMER_2_CNT_L, TIMER_3_CNT_H, TIMER_3_CNT_L, V_COUNT, WAVE_RAM,
+};
+use super::apu::WAVE_RAM_SIZE;
 use crate::{
     gba::system::SystemMemory,
     memory::{MemoryError, Memory},
     utils::Bitable,
 };
 
+pub const IRQ_V_BLANK: u32 = 1 << 0;
+pub const IRQ_H_BLANK: u32 = 1 << 1;
+pub const IRQ_V_COUNTER: u32 = 1 << 2;
+pub const IRQ_TIMER_0: u32 = 1 << 3;
+pub const IRQ_DMA_0: u32 = 1 << 8;
+pub const IRQ_KEYPAD: u32 = 1 << 12;
+
 pub struct InterruptMasterEnable(bool);
 
+/// Subsystems that need to know when one of their registers is written
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum IoEvent {
+    /// Holds the affine background, 2 or 3
+    AffineReference(usize),
+    DmaControl(usize),
+    TimerReload(usize),
+    TimerControl(usize),
+    KeypadControl,
+    InterruptEnable,
+    HaltControl,
+    /// Holds the address of the sound register
+    Sound(usize),
+    /// Holds the address of the FIFO
+    SoundFifo(usize),
+}
+
+/// How a CPU write to a halfword in io ram behaves. Each mask is for the bits of the halfword
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct IoRegister {
+    /// Bits the CPU can't change
+    pub readonly: u32,
+    /// Bits that are acted on but never stored, so they read back as 0
+    pub writeonly: u32,
+    /// Bits that are cleared by writing a 1 to them
+    pub write_one_to_clear: u32,
+    pub event: Option<IoEvent>,
+}
+
+impl IoRegister {
+    fn readonly(mask: u32) -> Self {
+        Self { readonly: mask, ..Self::default() }
+    }
+
+    fn event(event: IoEvent) -> Self {
+        Self { event: Some(event), ..Self::default() }
+    }
+}
+
+/// The dispatch table for io ram, `address` is the address of a halfword
+pub fn io_register(address: usize) -> IoRegister {
+    match address {
+        // Writing the reference point also reloads the internal reference point
+        a if (BG2_POINT_X..BG2_POINT_Y + 4).contains(&a) => IoRegister::event(IoEvent::AffineReference(2)),
+        a if (BG3_POINT_X..BG3_POINT_Y + 4).contains(&a) => IoRegister::event(IoEvent::AffineReference(3)),
+        // The CGB mode bit can only be set by the BIOS
+        DISP_CONTROL => IoRegister::readonly(0x8),
+        // V_BLANK, H_BLANK and V_COUNTER flags
+        DISP_STAT => IoRegister::readonly(0x7),
+        V_COUNT => IoRegister::readonly(0xffff),
+        SOUND_1_CNT_L => IoRegister::event(IoEvent::Sound(address)),
+        // The lengths, frequencies and restart bits of the channels can't be read
+        SOUND_1_CNT_H | SOUND_2_CNT_L | SOUND_4_CNT_L => IoRegister {
+            writeonly: 0x3f,
+            ..IoRegister::event(IoEvent::Sound(address))
+        },
+        SOUND_1_CNT_X | SOUND_2_CNT_H | SOUND_3_CNT_X => IoRegister {
+            writeonly: 0x87ff,
+            ..IoRegister::event(IoEvent::Sound(address))
+        },
+        SOUND_3_CNT_L => IoRegister::event(IoEvent::Sound(address)),
+        SOUND_3_CNT_H => IoRegister {
+            writeonly: 0xff,
+            ..IoRegister::event(IoEvent::Sound(address))
+        },
+        SOUND_4_CNT_H => IoRegister {
+            writeonly: 0x8000,
+            ..IoRegister::event(IoEvent::Sound(address))
+        },
+        SOUND_CNT_L | SOUND_BIAS => IoRegister::event(IoEvent::Sound(address)),
+        // FIFO reset bits
+        SOUND_CNT_H => IoRegister {
+            writeonly: 0x8800,
+            ..IoRegister::event(IoEvent::Sound(address))
+        },
+        // Sound on flags for each channel
+        SOUND_CNT_X => IoRegister {
+            readonly: 0xf,
+            ..IoRegister::event(IoEvent::Sound(address))
+        },
+        a if (WAVE_RAM..WAVE_RAM + WAVE_RAM_SIZE).contains(&a) => IoRegister::event(IoEvent::Sound(a)),
+        a if a & !0b11 == FIFO_A || a & !0b11 == FIFO_B => IoRegister {
+            writeonly: 0xffff,
+            event: Some(IoEvent::SoundFifo(a & !0b11)),
+            ..IoRegister::default()
+        },
+        DMA_0_CNT_H => IoRegister::event(IoEvent::DmaControl(0)),
+        DMA_1_CNT_H => IoRegister::event(IoEvent::DmaControl(1)),
+        DMA_2_CNT_H => IoRegister::event(IoEvent::DmaControl(2)),
+        DMA_3_CNT_H => IoRegister::event(IoEvent::DmaControl(3)),
+        // The counter is stored here, the reload value is kept by the timer
+        TIMER_0_CNT_L => IoRegister { readonly: 0xffff, ..IoRegister::event(IoEvent::TimerReload(0)) },
+        TIMER_1_CNT_L => IoRegister { readonly: 0xffff, ..IoRegister::event(IoEvent::TimerReload(1)) },
+        TIMER_2_CNT_L => IoRegister { readonly: 0xffff, ..IoRegister::event(IoEvent::TimerReload(2)) },
+        TIMER_3_CNT_L => IoRegister { readonly: 0xffff, ..IoRegister::event(IoEvent::TimerReload(3)) },
+        TIMER_0_CNT_H => IoRegister::event(IoEvent::TimerControl(0)),
+        TIMER_1_CNT_H => IoRegister::event(IoEvent::TimerControl(1)),
+        TIMER_2_CNT_H => IoRegister::event(IoEvent::TimerControl(2)),
+        TIMER_3_CNT_H => IoRegister::event(IoEvent::TimerControl(3)),
+        KEY_INPUT => IoRegister::readonly(0xffff),
+        KEY_CNT => IoRegister::event(IoEvent::KeypadControl),
+        INTERRUPT_ENABLE => IoRegister::event(IoEvent::InterruptEnable),
+        INTERRUPT_REQUEST => IoRegister {
+            write_one_to_clear: 0x3fff,
+            ..IoRegister::default()
+        },
+        // POSTFLG is the low byte, HALTCNT is the high byte
+        a if a == HALT_CNT & !0b1 => IoRegister {
+            writeonly: 0xff00,
+            event: Some(IoEvent::HaltControl),
+            ..IoRegister::default()
+        },
+        _ => IoRegister::default(),
+    }
+}
+
 pub fn interrupt_enable(ram: &SystemMemory) -> Result<InterruptEnableOrRequest, MemoryError> {
     let data = ram.read_halfword(INTERRUPT_ENABLE)?;
     Ok(InterruptEnableOrRequest::from(data))
@@ -1,12 +1,16 @@
+pub mod apu;
 pub mod arm;
+pub mod backup;
 pub mod cpu;
 pub mod debugger;
+pub mod keypad;
 pub mod system;
 pub mod thumb;
 mod utils;
-mod dma;
+pub(crate) mod dma;
 mod error;
-mod mapped_io;
+pub(crate) mod mapped_io;
+mod timer;
 
 const EXCEPTION_VECTOR_RESET: usize = 0x0;
 const EXCEPTION_VECTOR_UNDF: usize = 0x4;
@@ -1,14 +1,39 @@
 use core::fmt;
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
 
 use crate::memory::{Memory, MemoryError};
-use super::dma::DmaControl;
+use super::dma::{
+    dma_control_address, dma_count_address, dma_destination_address, dma_source_address,
+    next_address, DmaChannel, DmaControl, DmaTrigger, DMA_ADDRESS_FIXED,
+    DMA_ADDRESS_INCREMENT_RELOAD, DMA_CHANNELS, DMA_SOUND_FIFO_COUNT, DMA_START_IMMEDIATE,
+};
+use super::backup::{Backup, FlashChip};
+use super::apu::{Apu, SampleBuffer, FIFOS, WAVE_RAM_SIZE};
+use super::keypad::{Button, Keypad, KeypadControl, KEYS_RELEASED};
+use super::mapped_io::{io_register, IoEvent, IRQ_DMA_0, IRQ_KEYPAD, IRQ_TIMER_0};
+use super::EXCEPTION_VECTOR_IRQ;
+use super::timer::{timer_counter_address, Timers};
+use crate::utils::io_registers::{
+    FIFO_A, INTERRUPT_ENABLE, INTERRUPT_MASTER_ENABLE, INTERRUPT_REQUEST, KEY_CNT, KEY_INPUT,
+    SOUND_1_CNT_L, SOUND_CNT_H, SOUND_CNT_X, WAVE_RAM,
+};
 use crate::utils::{WORD, HALFWORD, BYTE, KILOBYTE};
 
-const INTERNAL_DMA_CONTROL_0: usize = 0x0000ba;
-const INTERNAL_DMA_CONTROL_1: usize = 0x0000c6;
-const INTERNAL_DMA_CONTROL_2: usize = 0x0000d2;
-const INTERNAL_DMA_CONTROL_3: usize = 0x0000de;
+// NOTE: Used in place of the BIOS IRQ handler when no BIOS is loaded. It saves the
+// registers and jumps to the user handler at 0x3007ffc just like the BIOS does
+const BIOS_IRQ_HANDLER: [u32; 7] = [
+    0xe92d500f, // stmfd sp!, {r0-r3, r12, lr}
+    0xe3a00403, // mov r0, #0x3000000
+    0xe2800c7f, // add r0, r0, #0x7f00
+    0xe28fe000, // add lr, pc, #0
+    0xe590f0fc, // ldr pc, [r0, #0xfc]
+    0xe8bd500f, // ldmfd sp!, {r0-r3, r12, lr}
+    0xe25ef004, // subs pc, lr, #4
+];
+
+// ROMs bigger than this only leave the last 256 bytes of 0xd000000 to the EEPROM
+const EEPROM_LARGE_ROM_SIZE: usize = 16 * KILOBYTE * KILOBYTE;
+const EEPROM_LARGE_ROM_START: usize = 0xffff00;
 
 pub fn read_cycles_per_8_16(address: usize) -> u32 {
     let mem_type = address >> 24 & 0xf;
@@ -18,7 +43,7 @@ pub fn read_cycles_per_8_16(address: usize) -> u32 {
         0x5 | 0x6 => 1,
         // Might be differnet
         0x8..=0xd => 5,
-        0xe => 5,
+        0xe..=0xf => 5,
         _ => 1,
     }
 }
@@ -30,7 +55,8 @@ pub fn read_cycles_per_32(address: usize) -> u32 {
         0x5 | 0x6 => 2,
         // Might be differnet in certain cases?
         0x8..=0xd => 8,
-        0xe => 0,
+        // SRAM only has an 8 bit bus, so a word is still one access
+        0xe..=0xf => 5,
         _ => 1,
     }
 }
@@ -46,7 +72,18 @@ pub struct SystemMemory {
     oam: Vec<u32>,
     // TODO: do this later
     pak_rom: Vec<u32>,
-    cart_ram: Vec<u32>,
+    backup: Backup,
+    // Set when the backup chip is written, so the frontend knows to flush the .sav file
+    backup_written: bool,
+    dma_channels: [DmaChannel; DMA_CHANNELS],
+    timers: Timers,
+    keypad: Keypad,
+    halted: bool,
+    // Bit 0 is BG2 and bit 1 is BG3
+    affine_reference_writes: u32,
+    // Set when PAL RAM is written, so the PPU knows to reload its palette
+    palette_written: bool,
+    apu: Apu,
 }
 
 impl fmt::Debug for SystemMemory {
@@ -59,7 +96,7 @@ impl fmt::Debug for SystemMemory {
         write!(f, "vram: {}, ", self.vram.len())?;
         write!(f, "oam: {}, ", self.oam.len())?;
         write!(f, "pak_rom: {}, ", self.pak_rom.len())?;
-        write!(f, "art_ram: {}, ", self.cart_ram.len())
+        write!(f, "backup: {}, ", self.backup.data().len())
     }
 }
 
@@ -117,13 +154,28 @@ impl Memory for SystemMemory {
         let res = self.read_byte(address)? as i32;
         Ok(((res << 24) >> 24) as u32)
     }
+
+    fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        let ime = self.read_halfword(INTERRUPT_MASTER_ENABLE).unwrap_or(0);
+        let ie = self.read_halfword(INTERRUPT_ENABLE).unwrap_or(0);
+        let irq_flags = self.read_halfword(INTERRUPT_REQUEST).unwrap_or(0);
+        ime & 1 == 1 && ie & irq_flags != 0
+    }
 }
 
 impl SystemMemory {
     pub fn new() -> Self {
-        Self {
-            // Should be divded by 4 since u32 are already 4 bytes
-            system_rom: vec![0; (16 * KILOBYTE) / 4],
+        // Should be divded by 4 since u32 are already 4 bytes
+        let mut system_rom = vec![0; (16 * KILOBYTE) / 4];
+        let irq_vector = EXCEPTION_VECTOR_IRQ >> 2;
+        system_rom[irq_vector..irq_vector + BIOS_IRQ_HANDLER.len()].copy_from_slice(&BIOS_IRQ_HANDLER);
+
+        let mut memory = Self {
+            system_rom,
             ewram: vec![0; (256 * KILOBYTE) / 4],
             iwram: vec![0; (0x1000000) / 4],
             io_ram: vec![0; (1 * KILOBYTE) / 4],
@@ -131,8 +183,18 @@ impl SystemMemory {
             vram: vec![0; 96 * KILOBYTE],
             oam: vec![0; 1 * (KILOBYTE / 4)],
             pak_rom: vec![0; 16 * 1],
-            cart_ram: vec![0; 16 * 1],
-        }
+            backup: Backup::detect(&[]),
+            backup_written: false,
+            dma_channels: [DmaChannel::default(); DMA_CHANNELS],
+            timers: Timers::default(),
+            keypad: Keypad::default(),
+            halted: false,
+            affine_reference_writes: 0,
+            apu: Apu::default(),
+            palette_written: false,
+        };
+        memory.set_io_halfword(KEY_INPUT, KEYS_RELEASED);
+        memory
     }
 
     #[allow(unused)]
@@ -146,7 +208,15 @@ impl SystemMemory {
             vram: vec![0; 0],
             oam: vec![0; 0],
             pak_rom: vec![0; 0],
-            cart_ram: vec![0; 0],
+            backup: Backup::None,
+            backup_written: false,
+            dma_channels: [DmaChannel::default(); DMA_CHANNELS],
+            timers: Timers::default(),
+            keypad: Keypad::default(),
+            halted: false,
+            affine_reference_writes: 0,
+            apu: Apu::default(),
+            palette_written: false,
         }
     }
 
@@ -162,16 +232,37 @@ impl SystemMemory {
     }
 
     pub fn copy_game_pak(&mut self, game_pak: Vec<u32>) {
+        self.backup = Backup::detect(&game_pak);
         self.pak_rom = game_pak;
     }
 
-    fn get_readonly_mask(&self, addr: usize) -> Option<u32> {
-        match addr {
-            0x4000004 => Some(0xff0043),
-            0x4000080 => Some(0x88000000),
-            0x4000084 => Some(0x0000000b),
-            _ => None,
+    /// Replaces the Flash chip picked from the ROM, this has no effect on other save chips
+    pub fn set_flash_chip(&mut self, chip: FlashChip) {
+        self.backup.set_flash_chip(chip);
+    }
+
+    // NOTE: The EEPROM takes up all of 0xd000000, unless the ROM is bigger than 16 MiB
+    // and needs that space, then it's only at 0xdffff00 and up
+    fn is_eeprom_address(&self, address: usize) -> bool {
+        if address >> 24 & 0xf != 0xd || !self.backup.is_eeprom() {
+            return false;
         }
+        self.pak_rom.len() * 4 <= EEPROM_LARGE_ROM_SIZE
+            || address & 0xffffff >= EEPROM_LARGE_ROM_START
+    }
+
+    /// The contents of the backup chip, as stored in a .sav file
+    pub fn save_data(&self) -> &[u8] {
+        self.backup.data()
+    }
+
+    pub fn load_save_data(&mut self, save: &[u8]) {
+        self.backup.load(save);
+    }
+
+    /// True if the backup chip has been written since the last call
+    pub fn take_backup_write(&mut self) -> bool {
+        std::mem::take(&mut self.backup_written)
     }
 
     fn write_with_mask(
@@ -180,19 +271,62 @@ impl SystemMemory {
         block: u32,
         mask: u32,
     ) -> Result<(), MemoryError> {
+        if self.is_eeprom_address(address) {
+            if self.backup.write_eeprom(block) {
+                self.backup_written = true;
+            }
+            return Ok(());
+        }
+        if address >> 24 & 0xf >= 0xe {
+            // Only one byte makes it through the 8 bit bus, picked by the low address bits
+            let lane = match mask {
+                BYTE => 0,
+                HALFWORD => address & 0b1,
+                _ => address & 0b11,
+            };
+            if self.backup.write(address, (block >> (lane * 8)) as u8) {
+                self.backup_written = true;
+            }
+            return Ok(());
+        }
+
         // NOTE: the first byte is lopped off because of the memory mapping
         let i = (address & 0xffffff) >> 2;
         let shift = (address & 0x3) * 8;
 
         let old_data = self.read_from_mem(address)?;
-        // Make sure we don't overwrite readonly data
-        let write_only_block = if let Some(readonly_mask) = self.get_readonly_mask(address) {
-            old_data & readonly_mask | block & !readonly_mask
-        } else {
-            block
-        };
+        let written_bits = mask << shift;
+        let value = (block & mask) << shift;
+        let merged_data = (old_data & !written_bits) | value;
+        let mut new_data = merged_data;
+
+        // Each halfword in io ram can have its own write behavior
+        let mut events: [Option<(IoEvent, u32, u32)>; 2] = [None, None];
+        if address >> 24 & 0xf == 0x4 {
+            for (n, half_shift) in [0, 16].into_iter().enumerate() {
+                if written_bits & (HALFWORD << half_shift) == 0 {
+                    continue;
+                }
 
-        let new_data = (old_data & !(mask << shift)) | ((write_only_block & mask) << shift);
+                let register = io_register((address & !0b11) + (half_shift >> 3));
+                let readonly = register.readonly << half_shift;
+                let write_one_to_clear = register.write_one_to_clear << half_shift;
+
+                new_data = (new_data & !readonly) | (old_data & readonly);
+                new_data = (new_data & !write_one_to_clear) | (old_data & write_one_to_clear & !value);
+                // Events see the value as written, even for bits that don't get stored, along
+                // with which of its bits were written
+                events[n] = register
+                    .event
+                    // NOTE: Only writes to the HALTCNT byte halt, not writes to POSTFLG
+                    .filter(|e| *e != IoEvent::HaltControl || written_bits & (0xff00 << half_shift) != 0)
+                    .map(|e| {
+                        let data = (merged_data >> half_shift) & HALFWORD;
+                        (e, data, (written_bits >> half_shift) & HALFWORD)
+                    });
+                new_data &= !(register.writeonly << half_shift);
+            }
+        }
         trace!(
             "addr: {:x}, old value: {:x}, new_value: {:x}",
             address,
@@ -202,14 +336,152 @@ impl SystemMemory {
 
         let ram: &mut Vec<u32> = self.memory_map_mut(address)?;
         if i > ram.len() {
-            Err(MemoryError::OutOfBounds(address, i))
-        } else {
-            ram[i] = new_data;
-            Ok(())
+            return Err(MemoryError::OutOfBounds(address, i));
+        }
+        ram[i] = new_data;
+        if address >> 24 & 0xf == 0x5 {
+            self.palette_written = true;
+        }
+
+        for (event, data, written) in events.into_iter().flatten() {
+            self.handle_io_event(event, data, written)?;
+        }
+        Ok(())
+    }
+
+    fn handle_io_event(&mut self, event: IoEvent, data: u32, written: u32) -> Result<(), MemoryError> {
+        trace!("Handling {:?} with: {:x}", event, data);
+        match event {
+            IoEvent::DmaControl(channel) => {
+                self.update_dma_channel(channel)?;
+            }
+            IoEvent::TimerReload(timer) => {
+                // io ram holds the counter, so byte writes are merged with the reload value
+                let reload = self.timers.timers[timer].reload;
+                self.timers.write_reload(timer, (reload & !written) | (data & written));
+            }
+            IoEvent::TimerControl(timer) => {
+                self.timers.write_control(timer, data);
+                let counter = self.timers.timers[timer].counter;
+                self.set_io_halfword(timer_counter_address(timer), counter);
+            }
+            IoEvent::AffineReference(bg) => self.affine_reference_writes |= 1 << (bg - 2),
+            IoEvent::KeypadControl => self.update_keypad_interrupt(),
+            IoEvent::InterruptEnable => self.update_halt(),
+            IoEvent::HaltControl => {
+                // NOTE: Stop mode is treated the same as halt
+                self.halted = true;
+                self.update_halt();
+            }
+            IoEvent::Sound(address) => self.write_sound(address, data),
+            IoEvent::SoundFifo(address) => self.apu.write_fifo(address, data, written),
+        }
+        Ok(())
+    }
+
+    // The CPU wakes up from halt when any enabled interrupt is requested, even if IME is off
+    fn update_halt(&mut self) {
+        let ie = self.read_halfword(INTERRUPT_ENABLE).unwrap_or(0);
+        let irq_flags = self.read_halfword(INTERRUPT_REQUEST).unwrap_or(0);
+        if self.halted && ie & irq_flags != 0 {
+            trace!("Waking up from halt");
+            self.halted = false;
+        }
+    }
+
+    /// Runs everything on the bus that is clocked by the CPU
+    pub fn tick(&mut self, cycle: u32) {
+        let overflows = self.timers.tick(cycle);
+        for (timer, overflow) in overflows.into_iter().enumerate() {
+            let counter = self.timers.timers[timer].counter;
+            self.set_io_halfword(timer_counter_address(timer), counter);
+
+            if overflow > 0 && self.timers.timers[timer].control.irq {
+                self.request_interrupt(IRQ_TIMER_0 << timer);
+            }
+            if overflow > 0 && timer < 2 {
+                self.play_fifos(timer, overflow);
+            }
+        }
+
+        self.apu.tick(cycle);
+        self.update_sound_status();
+    }
+
+    /// The mixed sound output, which the frontend should drain every frame
+    pub fn audio_samples(&mut self) -> &mut SampleBuffer {
+        self.apu.samples()
+    }
+
+    /// Starts recording each sound channel on its own as well as the mix
+    pub fn record_stems(&mut self) {
+        self.apu.record_stems();
+    }
+
+    /// The recording of each sound channel, empty until `record_stems` is called
+    pub fn stem_samples(&mut self) -> &mut [SampleBuffer] {
+        self.apu.stems()
+    }
+
+    /// Direct Sound plays a sample on each overflow of the FIFO's timer
+    fn play_fifos(&mut self, timer: usize, overflows: u32) {
+        let refill = self.apu.timer_overflow(timer, overflows);
+        for fifo in (0..FIFOS).filter(|fifo| refill[*fifo]) {
+            let trigger = DmaTrigger::SoundFifo(FIFO_A + fifo * 4);
+            if let Err(e) = self.trigger_dma(trigger) {
+                warn!("Failed to refill FIFO: {}", e);
+            }
         }
     }
 
+    fn write_sound(&mut self, address: usize, data: u32) {
+        let bank = self.apu.wave_bank();
+        self.apu.write(address, data);
+
+        let is_psg_register = (SOUND_1_CNT_L..SOUND_CNT_H).contains(&address);
+        if address == SOUND_CNT_X && !self.apu.enabled {
+            // Turning the APU off clears the PSG registers
+            for register in (SOUND_1_CNT_L..SOUND_CNT_H).step_by(2) {
+                self.set_io_halfword(register, 0);
+            }
+        } else if is_psg_register && !self.apu.enabled {
+            self.set_io_halfword(address, 0);
+        }
+
+        // The CPU can only see the wave RAM bank that isn't being played
+        if self.apu.wave_bank() != bank {
+            for offset in (0..WAVE_RAM_SIZE).step_by(2) {
+                self.set_io_halfword(WAVE_RAM + offset, self.apu.read_wave_ram(offset));
+            }
+        }
+        self.update_sound_status();
+    }
+
+    /// Copies the sound on flags into SOUNDCNT_X
+    fn update_sound_status(&mut self) {
+        let sound_cnt_x = self.read_halfword(SOUND_CNT_X).unwrap_or(0);
+        let status = (sound_cnt_x & !0xf) | self.apu.channels_on();
+        if status != sound_cnt_x {
+            self.set_io_halfword(SOUND_CNT_X, status);
+        }
+    }
+
+    // Writes directly to io ram without triggering any of the register side effects
+    fn set_io_halfword(&mut self, address: usize, value: u32) {
+        let idx = (address & 0xffffff) >> 2;
+        let shift = (address & 0x2) * 8;
+        self.io_ram[idx] = (self.io_ram[idx] & !(HALFWORD << shift)) | ((value & HALFWORD) << shift);
+    }
+
     pub fn read_from_mem(&self, address: usize) -> Result<u32, MemoryError> {
+        if self.is_eeprom_address(address) {
+            // NOTE: Only DMA reads the EEPROM's data, the CPU just sees the ready bit
+            return Ok(1);
+        }
+        if address >> 24 & 0xf >= 0xe {
+            // The byte is repeated across the whole bus
+            return Ok(self.backup.read(address) as u32 * 0x01010101);
+        }
         let ram: &Vec<u32> = self.memory_map(address)?;
         let mem_address = (address & 0xffffff) >> 2;
 
@@ -222,18 +494,188 @@ impl SystemMemory {
         }
     }
 
+    /// Sets the given bits in IF
+    pub fn request_interrupt(&mut self, flags: u32) {
+        let idx = (INTERRUPT_REQUEST & 0xffffff) >> 2;
+        let shift = (INTERRUPT_REQUEST & 0x3) * 8;
+        self.io_ram[idx] |= (flags & HALFWORD) << shift;
+        self.update_halt();
+    }
+
+    /// Returns which affine reference points were written since the last call, bit 0 is BG2
+    /// and bit 1 is BG3. The PPU uses this to reload its internal reference points
+    pub fn take_affine_reference_writes(&mut self) -> u32 {
+        std::mem::take(&mut self.affine_reference_writes)
+    }
+
+    /// Returns true if PAL RAM was written since the last call
+    pub fn take_palette_write(&mut self) -> bool {
+        std::mem::take(&mut self.palette_written)
+    }
+
+    /// Presses or releases one of the GBA buttons, this is how frontends and headless runs
+    /// give the game input
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.keypad.set_button(button, pressed);
+        self.set_io_halfword(KEY_INPUT, self.keypad.key_input());
+        self.update_keypad_interrupt();
+    }
+
+    fn update_keypad_interrupt(&mut self) {
+        let control = KeypadControl::from(self.read_halfword(KEY_CNT).unwrap_or(0));
+        if self.keypad.irq_condition(&control) {
+            self.request_interrupt(IRQ_KEYPAD);
+        }
+    }
+
+    fn dma_control(&self, channel: usize) -> Result<DmaControl, MemoryError> {
+        let data = self.read_halfword(dma_control_address(channel))?;
+        Ok(DmaControl::from(data))
+    }
+
     pub fn is_dma_enabled(&self) -> bool {
-        let mut enabled = false;
-        enabled = DmaControl::from(self.io_ram[INTERNAL_DMA_CONTROL_0]).dma_enabled || enabled;
-        enabled = DmaControl::from(self.io_ram[INTERNAL_DMA_CONTROL_1]).dma_enabled || enabled;
-        enabled = DmaControl::from(self.io_ram[INTERNAL_DMA_CONTROL_2]).dma_enabled || enabled;
-        enabled = DmaControl::from(self.io_ram[INTERNAL_DMA_CONTROL_3]).dma_enabled || enabled;
-        enabled
+        (0..DMA_CHANNELS).any(|c| self.dma_control(c).is_ok_and(|dc| dc.dma_enabled))
+    }
+
+    // Latches the DMA registers into the internal ones when a channel has just been enabled
+    fn update_dma_channel(&mut self, channel: usize) -> Result<DmaControl, MemoryError> {
+        let control = self.dma_control(channel)?;
+        if !control.dma_enabled {
+            self.dma_channels[channel].running = false;
+            self.dma_channels[channel].pending = false;
+        } else if !self.dma_channels[channel].running {
+            let source = self.read_word(dma_source_address(channel))?;
+            let destination = self.read_word(dma_destination_address(channel))?;
+            let count = self.read_halfword(dma_count_address(channel))?;
+            self.dma_channels[channel].latch(channel, source, destination, count);
+        }
+        Ok(control)
+    }
+
+    /// Marks every enabled channel that starts on `trigger` as ready to run
+    pub fn trigger_dma(&mut self, trigger: DmaTrigger) -> Result<(), MemoryError> {
+        for channel in 0..DMA_CHANNELS {
+            let control = self.update_dma_channel(channel)?;
+            if !control.dma_enabled || !control.is_triggered_by(channel, trigger) {
+                continue;
+            }
+
+            // NOTE: DAD can be rewritten while the channel runs, only the latched address counts
+            if let DmaTrigger::SoundFifo(fifo) = trigger {
+                if self.dma_channels[channel].destination as usize != fifo {
+                    continue;
+                }
+            }
+
+            trace!("DMA{} triggered by {:?}", channel, trigger);
+            self.dma_channels[channel].pending = true;
+        }
+        Ok(())
+    }
+
+    /// Video capture DMA is stopped by hardware once the capture lines are done
+    pub fn stop_video_capture_dma(&mut self) -> Result<(), MemoryError> {
+        let control = self.update_dma_channel(3)?;
+        if control.dma_enabled && control.is_triggered_by(3, DmaTrigger::VideoCapture) {
+            self.disable_dma(3);
+        }
+        Ok(())
+    }
+
+    fn disable_dma(&mut self, channel: usize) {
+        self.dma_channels[channel].running = false;
+        self.dma_channels[channel].pending = false;
+        let idx = (dma_control_address(channel) & 0xffffff) >> 2;
+        let shift = (dma_control_address(channel) & 0x3) * 8;
+        self.io_ram[idx] &= !(1 << (15 + shift));
     }
 
     // should return cycles run
     pub fn run_dma(&mut self) -> Result<u32, MemoryError> {
-        todo!()
+        let mut cycles = 0;
+
+        // NOTE: DMA0 has the highest priority, so it's always run first
+        for channel in 0..DMA_CHANNELS {
+            let control = self.update_dma_channel(channel)?;
+            if !control.dma_enabled {
+                continue;
+            }
+
+            if control.dma_start_timing == DMA_START_IMMEDIATE
+                || self.dma_channels[channel].pending
+            {
+                cycles += self.transfer_dma(channel, &control)?;
+            }
+        }
+
+        Ok(cycles)
+    }
+
+    fn transfer_dma(&mut self, channel: usize, control: &DmaControl) -> Result<u32, MemoryError> {
+        let mut dma = self.dma_channels[channel];
+        dma.pending = false;
+
+        let sound_fifo = control.is_sound_fifo(channel);
+        let (count, unit_size, destination_control) = if sound_fifo {
+            (DMA_SOUND_FIFO_COUNT, 4, DMA_ADDRESS_FIXED)
+        } else {
+            (dma.count, control.unit_size(), control.destination_control)
+        };
+        trace!(
+            "DMA{} transfering {} units from {:x} to {:x}",
+            channel,
+            count,
+            dma.source,
+            dma.destination
+        );
+
+        if self.is_eeprom_address(dma.destination as usize) {
+            self.backup.start_eeprom_transfer(count);
+        }
+
+        // NOTE: 2I for the DMA to start up
+        let mut cycles = 2;
+        for _ in 0..count {
+            if unit_size == 4 {
+                let data = self.read_word((dma.source & !0b11) as usize)?;
+                self.write_word((dma.destination & !0b11) as usize, data)?;
+                cycles += read_cycles_per_32(dma.source as usize)
+                    + read_cycles_per_32(dma.destination as usize);
+            } else {
+                // The EEPROM sends its bits one at a time
+                let data = if self.is_eeprom_address(dma.source as usize) {
+                    self.backup.read_eeprom()
+                } else {
+                    self.read_halfword((dma.source & !0b1) as usize)?
+                };
+                self.write_halfword((dma.destination & !0b1) as usize, data)?;
+                cycles += read_cycles_per_8_16(dma.source as usize)
+                    + read_cycles_per_8_16(dma.destination as usize);
+            }
+
+            dma.source = next_address(dma.source, control.source_control, unit_size);
+            dma.destination = next_address(dma.destination, destination_control, unit_size);
+        }
+
+        if control.irq {
+            self.request_interrupt(IRQ_DMA_0 << channel);
+        }
+
+        // NOTE: Repeat is ignored for immediate transfers
+        if control.dma_repeat && control.dma_start_timing != DMA_START_IMMEDIATE {
+            if !sound_fifo {
+                dma.reload_count(channel, self.read_halfword(dma_count_address(channel))?);
+            }
+            if control.destination_control == DMA_ADDRESS_INCREMENT_RELOAD {
+                dma.reload_destination(channel, self.read_word(dma_destination_address(channel))?);
+            }
+            self.dma_channels[channel] = dma;
+        } else {
+            self.dma_channels[channel] = dma;
+            self.disable_dma(channel);
+        }
+
+        Ok(cycles)
     }
 
     //TODO: Make another that isn't mut?
@@ -249,7 +691,6 @@ impl SystemMemory {
             0x6 => Ok(&self.vram),
             0x7 => Ok(&self.oam),
             0x8..=0xd => Ok(&self.pak_rom),
-            0xe => Ok(&self.cart_ram),
             _ => Err(MemoryError::MapNotFound(address)),
         }
     }
@@ -265,7 +706,6 @@ impl SystemMemory {
             0x6 => Ok(&self.vram.as_slice()),
             0x7 => Ok(&self.oam.as_slice()),
             0x8..=0xd => Ok(&self.pak_rom.as_slice()),
-            0xe => Ok(&self.cart_ram.as_slice()),
             _ => Err(MemoryError::MapNotFound(address)),
         }
     }
@@ -281,7 +721,6 @@ impl SystemMemory {
             0x6 => Ok(&mut self.vram),
             0x7 => Ok(&mut self.oam),
             0x8..=0xd => Ok(&mut self.pak_rom),
-            0xe => Ok(&mut self.cart_ram),
             _ => Err(MemoryError::MapNotFound(address)),
         }
     }
@@ -306,3 +745,376 @@ impl SystemMemory {
         &self.pal_ram.as_slice()
     }
 }
+
+mod test {
+    #![allow(unused)]
+    use super::{Apu, SystemMemory};
+    use crate::gba::dma::DmaTrigger;
+    use crate::gba::keypad::Button;
+    use crate::memory::Memory;
+    use crate::utils::io_registers::{
+        BG2_POINT_X, BG3_POINT_Y, DISP_STAT, DMA_0_CNT_H, DMA_0_CNT_L, DMA_0_DAD, DMA_0_SAD,
+        DMA_1_CNT_H, DMA_1_CNT_L, DMA_1_DAD, DMA_1_SAD, DMA_3_CNT_H, DMA_3_CNT_L, DMA_3_DAD,
+        DMA_3_SAD, FIFO_A, HALT_CNT, INTERRUPT_ENABLE, INTERRUPT_REQUEST, KEY_CNT, KEY_INPUT,
+        SOUND_2_CNT_H, SOUND_2_CNT_L, SOUND_3_CNT_L, SOUND_CNT_H, SOUND_CNT_X, TIMER_0_CNT_L,
+        TIMER_1_CNT_H, TIMER_1_CNT_L,
+        V_COUNT, WAVE_RAM,
+    };
+
+    #[test]
+    fn run_dma3_immediate_word_transfer() {
+        let mut ram = SystemMemory::new();
+        for i in 0..4 {
+            ram.write_word(0x2000000 + i * 4, 0x11111111 * (i as u32 + 1)).unwrap();
+        }
+
+        ram.write_word(DMA_3_SAD, 0x2000000).unwrap();
+        ram.write_word(DMA_3_DAD, 0x6000000).unwrap();
+        ram.write_halfword(DMA_3_CNT_L, 4).unwrap();
+        ram.write_halfword(DMA_3_CNT_H, 0x8400).unwrap();
+
+        assert!(ram.is_dma_enabled());
+        let cycles = ram.run_dma().unwrap();
+
+        assert_eq!(0x11111111, ram.read_word(0x6000000).unwrap());
+        assert_eq!(0x22222222, ram.read_word(0x6000004).unwrap());
+        assert_eq!(0x33333333, ram.read_word(0x6000008).unwrap());
+        assert_eq!(0x44444444, ram.read_word(0x600000c).unwrap());
+        assert_eq!(0x0, ram.read_word(0x6000010).unwrap());
+        assert_eq!(2 + 4 * (6 + 2), cycles);
+        assert!(!ram.is_dma_enabled());
+    }
+
+    #[test]
+    fn run_dma_halfword_fixed_source_decrement_destination() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(0x3000000, 0xbeef).unwrap();
+
+        ram.write_word(DMA_0_SAD, 0x3000000).unwrap();
+        ram.write_word(DMA_0_DAD, 0x3000106).unwrap();
+        ram.write_halfword(DMA_0_CNT_L, 3).unwrap();
+        // Decrement destination, fixed source
+        ram.write_halfword(DMA_0_CNT_H, 0x8120).unwrap();
+
+        ram.run_dma().unwrap();
+
+        assert_eq!(0xbeef, ram.read_halfword(0x3000106).unwrap());
+        assert_eq!(0xbeef, ram.read_halfword(0x3000104).unwrap());
+        assert_eq!(0xbeef, ram.read_halfword(0x3000102).unwrap());
+        assert_eq!(0x0, ram.read_halfword(0x3000100).unwrap());
+    }
+
+    #[test]
+    fn run_dma_requests_interrupt() {
+        let mut ram = SystemMemory::new();
+
+        ram.write_word(DMA_3_SAD, 0x2000000).unwrap();
+        ram.write_word(DMA_3_DAD, 0x2000100).unwrap();
+        ram.write_halfword(DMA_3_CNT_L, 1).unwrap();
+        ram.write_halfword(DMA_3_CNT_H, 0xc000).unwrap();
+
+        ram.run_dma().unwrap();
+
+        assert_eq!(1 << 11, ram.read_halfword(INTERRUPT_REQUEST).unwrap());
+    }
+
+    #[test]
+    fn run_dma_h_blank_repeat_reloads_destination() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(0x2000000, 0x1234).unwrap();
+        ram.write_halfword(0x2000002, 0x5678).unwrap();
+
+        ram.write_word(DMA_0_SAD, 0x2000000).unwrap();
+        ram.write_word(DMA_0_DAD, 0x4000010).unwrap();
+        ram.write_halfword(DMA_0_CNT_L, 1).unwrap();
+        // HBlank, repeat, increment/reload destination
+        ram.write_halfword(DMA_0_CNT_H, 0xa260).unwrap();
+
+        assert_eq!(0, ram.run_dma().unwrap());
+        assert_eq!(0, ram.read_halfword(0x4000010).unwrap());
+
+        ram.trigger_dma(DmaTrigger::VBlank).unwrap();
+        assert_eq!(0, ram.run_dma().unwrap());
+
+        ram.trigger_dma(DmaTrigger::HBlank).unwrap();
+        ram.run_dma().unwrap();
+        assert_eq!(0x1234, ram.read_halfword(0x4000010).unwrap());
+        assert_eq!(0, ram.run_dma().unwrap());
+
+        ram.trigger_dma(DmaTrigger::HBlank).unwrap();
+        ram.run_dma().unwrap();
+        assert_eq!(0x5678, ram.read_halfword(0x4000010).unwrap());
+        assert_eq!(0, ram.read_halfword(0x4000012).unwrap());
+        assert!(ram.is_dma_enabled());
+    }
+
+    #[test]
+    fn run_dma_sound_fifo() {
+        let mut ram = SystemMemory::new();
+
+        ram.write_word(DMA_1_SAD, 0x2000000).unwrap();
+        ram.write_word(DMA_1_DAD, FIFO_A as u32).unwrap();
+        ram.write_halfword(DMA_1_CNT_L, 1).unwrap();
+        // Special, repeat, halfword sized
+        ram.write_halfword(DMA_1_CNT_H, 0xb200).unwrap();
+
+        ram.trigger_dma(DmaTrigger::SoundFifo(0x40000a4)).unwrap();
+        assert_eq!(0, ram.run_dma().unwrap());
+
+        ram.trigger_dma(DmaTrigger::SoundFifo(FIFO_A)).unwrap();
+        // 4 words from ewram to io
+        assert_eq!(2 + 4 * (6 + 1), ram.run_dma().unwrap());
+        assert!(ram.is_dma_enabled());
+
+        // Rewriting DAD doesn't move a running channel to the other FIFO
+        ram.write_word(DMA_1_DAD, 0x40000a4).unwrap();
+        ram.trigger_dma(DmaTrigger::SoundFifo(0x40000a4)).unwrap();
+        assert_eq!(0, ram.run_dma().unwrap());
+        ram.trigger_dma(DmaTrigger::SoundFifo(FIFO_A)).unwrap();
+        assert_eq!(2 + 4 * (6 + 1), ram.run_dma().unwrap());
+    }
+
+    #[test]
+    fn sram_uses_an_8_bit_bus() {
+        let mut ram = SystemMemory::new();
+        // Wider writes only store the byte picked by the address
+        ram.write_word(0xe000001, 0x44332211).unwrap();
+        ram.write_halfword(0xe000003, 0x6655).unwrap();
+        ram.write_byte(0xe000004, 0x77).unwrap();
+        assert_eq!(&[0xff, 0x22, 0xff, 0x66, 0x77], &ram.save_data()[..5]);
+        assert!(ram.take_backup_write());
+        assert!(!ram.take_backup_write());
+
+        // Wider reads see the byte repeated
+        assert_eq!(0x22222222, ram.read_word(0xe000001).unwrap());
+        assert_eq!(0x6666, ram.read_halfword(0xe008003).unwrap());
+        assert_eq!(0x77, ram.read_byte(0xf000004).unwrap());
+
+        // Writing the same byte again doesn't need a save
+        ram.write_byte(0xe000004, 0x77).unwrap();
+        assert!(!ram.take_backup_write());
+    }
+
+    #[test]
+    fn eeprom_over_dma3() {
+        let mut ram = SystemMemory::new();
+        // "EEPROM_V124"
+        ram.copy_game_pak(vec![0x52504545, 0x565f4d4f, 0x343231]);
+        let dma3 = |ram: &mut SystemMemory, source: u32, destination: u32, count: u32| {
+            ram.write_word(DMA_3_SAD, source).unwrap();
+            ram.write_word(DMA_3_DAD, destination).unwrap();
+            ram.write_halfword(DMA_3_CNT_L, count).unwrap();
+            ram.write_halfword(DMA_3_CNT_H, 0x8000).unwrap();
+            ram.run_dma().unwrap();
+        };
+
+        // A 73 bit write request to block 1 of a 512 byte EEPROM
+        let data: u64 = 0x8000_0000_0000_0001;
+        let mut bits = vec![1, 0, 0, 0, 0, 0, 0, 1];
+        bits.extend((0..64).rev().map(|i| (data >> i) as u32 & 1));
+        bits.push(0);
+        for (i, bit) in bits.iter().enumerate() {
+            ram.write_halfword(0x2000000 + i * 2, *bit).unwrap();
+        }
+        dma3(&mut ram, 0x2000000, 0xd000000, 73);
+        assert_eq!(0x200, ram.save_data().len());
+        assert_eq!(&[0x80, 0, 0, 0, 0, 0, 0, 0x01], &ram.save_data()[8..16]);
+        assert!(ram.take_backup_write());
+        assert_eq!(1, ram.read_halfword(0xd000000).unwrap());
+
+        // A 9 bit read request, then 68 bits back
+        let request = [1, 1, 0, 0, 0, 0, 0, 1, 0];
+        for (i, bit) in request.iter().enumerate() {
+            ram.write_halfword(0x2000000 + i * 2, *bit).unwrap();
+        }
+        dma3(&mut ram, 0x2000000, 0xd000000, 9);
+        dma3(&mut ram, 0xd000000, 0x2000100, 68);
+        let read: Vec<u32> = (0..68)
+            .map(|i| ram.read_halfword(0x2000100 + i * 2).unwrap())
+            .collect();
+        let block = read[4..].iter().fold(0u64, |acc, b| (acc << 1) | *b as u64);
+        assert_eq!(&[0, 0, 0, 0], &read[..4]);
+        assert_eq!(data, block);
+    }
+
+    #[test]
+    fn eeprom_leaves_large_roms_mapped() {
+        let mut ram = SystemMemory::new();
+        // "EEPROM_V124" in a ROM just over 16 MiB
+        let mut rom = vec![0x52504545, 0x565f4d4f, 0x343231];
+        rom.resize(0x400001, 0x12345678);
+        ram.copy_game_pak(rom);
+
+        assert_eq!(0x12345678, ram.read_word(0xd000010).unwrap());
+        assert_eq!(1, ram.read_halfword(0xdffff00).unwrap());
+    }
+
+    #[test]
+    fn tick_timer_overflow_refills_fifo() {
+        let mut ram = SystemMemory::new();
+        ram.write_word(DMA_1_SAD, 0x2000000).unwrap();
+        ram.write_word(DMA_1_DAD, FIFO_A as u32).unwrap();
+        ram.write_halfword(DMA_1_CNT_H, 0xb600).unwrap();
+        // FIFO A on timer 0, which overflows every cycle
+        ram.write_halfword(SOUND_CNT_H, 0x0300).unwrap();
+        ram.write_word(TIMER_0_CNT_L, 0x0080ffff).unwrap();
+        ram.write_word(FIFO_A, 0x00000080).unwrap();
+        assert_eq!(0, ram.read_word(FIFO_A).unwrap());
+
+        ram.tick(1);
+        assert_eq!(2 + 4 * (6 + 1), ram.run_dma().unwrap());
+    }
+
+    #[test]
+    fn tick_timer_overflow_requests_interrupt() {
+        let mut ram = SystemMemory::new();
+        ram.write_word(TIMER_1_CNT_L, 0x00c0fff0).unwrap();
+
+        ram.tick(0xf);
+        assert_eq!(0xffff, ram.read_halfword(TIMER_1_CNT_L).unwrap());
+        assert_eq!(0, ram.read_halfword(INTERRUPT_REQUEST).unwrap());
+
+        ram.tick(0x10);
+        assert_eq!(0xfff0, ram.read_halfword(TIMER_1_CNT_L).unwrap());
+        assert_eq!(1 << 4, ram.read_halfword(INTERRUPT_REQUEST).unwrap());
+
+        // Writing CNT_L only changes the reload value
+        ram.write_halfword(TIMER_1_CNT_L, 0x1234).unwrap();
+        assert_eq!(0xfff0, ram.read_halfword(TIMER_1_CNT_L).unwrap());
+        ram.write_halfword(TIMER_1_CNT_H, 0).unwrap();
+        ram.write_halfword(TIMER_1_CNT_H, 0x80).unwrap();
+        assert_eq!(0x1234, ram.read_halfword(TIMER_1_CNT_L).unwrap());
+    }
+
+    #[test]
+    fn write_timer_reload_bytes() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(TIMER_0_CNT_L, 0x1234).unwrap();
+        ram.write_byte(TIMER_0_CNT_L + 1, 0x56).unwrap();
+        assert_eq!(0x5634, ram.timers.timers[0].reload);
+        ram.write_byte(TIMER_0_CNT_L, 0x78).unwrap();
+        assert_eq!(0x5678, ram.timers.timers[0].reload);
+    }
+
+    #[test]
+    fn write_fifo_bytes() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(SOUND_CNT_H, 0x0300).unwrap();
+        // Each byte write queues a single sample
+        ram.write_byte(FIFO_A + 1, 0x7f).unwrap();
+        ram.write_byte(FIFO_A, 0x80).unwrap();
+
+        let mut apu = Apu::default();
+        apu.write(SOUND_CNT_H, 0x0300);
+        apu.write_fifo(FIFO_A, 0x807f, 0xffff);
+        assert_eq!(apu, ram.apu);
+    }
+
+    #[test]
+    fn write_interrupt_request_acknowledges() {
+        let mut ram = SystemMemory::new();
+        ram.request_interrupt(0b1011);
+
+        ram.write_halfword(INTERRUPT_REQUEST, 0b0010).unwrap();
+        assert_eq!(0b1001, ram.read_halfword(INTERRUPT_REQUEST).unwrap());
+
+        // Writing IE in the same word leaves the unacknowledged flags alone
+        ram.write_word(INTERRUPT_ENABLE, 0x0001_ffff).unwrap();
+        assert_eq!(0b1000, ram.read_halfword(INTERRUPT_REQUEST).unwrap());
+        assert_eq!(0xffff, ram.read_halfword(INTERRUPT_ENABLE).unwrap());
+    }
+
+    #[test]
+    fn write_readonly_registers() {
+        let mut ram = SystemMemory::new();
+        ram.get_io_ram()[1] = 0x0000_0000;
+
+        ram.write_word(DISP_STAT, 0xffff_ffff).unwrap();
+        assert_eq!(0xfff8, ram.read_halfword(DISP_STAT).unwrap());
+        assert_eq!(0, ram.read_halfword(V_COUNT).unwrap());
+    }
+
+    #[test]
+    fn write_halt_control_waits_for_interrupt() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(INTERRUPT_ENABLE, 1 << 4).unwrap();
+
+        ram.write_byte(HALT_CNT, 0).unwrap();
+        assert!(ram.is_halted());
+
+        // Interrupts that aren't enabled don't wake the CPU
+        ram.request_interrupt(1 << 3);
+        assert!(ram.is_halted());
+
+        ram.request_interrupt(1 << 4);
+        assert!(!ram.is_halted());
+
+        // Writing POSTFLG alone doesn't halt
+        ram.write_byte(HALT_CNT - 1, 1).unwrap();
+        assert!(!ram.is_halted());
+    }
+
+    #[test]
+    fn set_button_updates_key_input() {
+        let mut ram = SystemMemory::new();
+        assert_eq!(0x3ff, ram.read_halfword(KEY_INPUT).unwrap());
+
+        ram.set_button(Button::Start, true);
+        ram.set_button(Button::Up, true);
+        assert_eq!(0x3b7, ram.read_halfword(KEY_INPUT).unwrap());
+
+        // The CPU can't write KEYINPUT
+        ram.write_halfword(KEY_INPUT, 0).unwrap();
+        assert_eq!(0x3b7, ram.read_halfword(KEY_INPUT).unwrap());
+    }
+
+    #[test]
+    fn set_button_requests_keypad_interrupt() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(KEY_CNT, 0xc000 | Button::A.bit() | Button::B.bit()).unwrap();
+
+        ram.set_button(Button::A, true);
+        assert_eq!(0, ram.read_halfword(INTERRUPT_REQUEST).unwrap());
+
+        ram.set_button(Button::B, true);
+        assert_eq!(1 << 12, ram.read_halfword(INTERRUPT_REQUEST).unwrap());
+    }
+
+    #[test]
+    fn write_affine_reference_is_reported_once() {
+        let mut ram = SystemMemory::new();
+        ram.write_halfword(BG2_POINT_X + 2, 0x1).unwrap();
+        ram.write_word(BG3_POINT_Y, 0x100).unwrap();
+
+        assert_eq!(0b11, ram.take_affine_reference_writes());
+        assert_eq!(0, ram.take_affine_reference_writes());
+    }
+
+    #[test]
+    fn write_sound_registers() {
+        let mut ram = SystemMemory::new();
+        // PSG writes are ignored while the APU is off
+        ram.write_halfword(SOUND_2_CNT_L, 0xf000).unwrap();
+        assert_eq!(0, ram.read_halfword(SOUND_2_CNT_L).unwrap());
+
+        ram.write_halfword(SOUND_CNT_X, 0x80).unwrap();
+        ram.write_halfword(SOUND_2_CNT_L, 0xf03f).unwrap();
+        ram.write_halfword(SOUND_2_CNT_H, 0x87ff).unwrap();
+        // The length and frequency can't be read, but the channel is on
+        assert_eq!(0xf000, ram.read_halfword(SOUND_2_CNT_L).unwrap());
+        assert_eq!(0, ram.read_halfword(SOUND_2_CNT_H).unwrap());
+        assert_eq!(0x82, ram.read_halfword(SOUND_CNT_X).unwrap());
+
+        // Switching the bank shows the other half of wave RAM
+        ram.write_halfword(WAVE_RAM, 0x1234).unwrap();
+        ram.write_halfword(SOUND_3_CNT_L, 0x40).unwrap();
+        assert_eq!(0, ram.read_halfword(WAVE_RAM).unwrap());
+        ram.write_halfword(SOUND_3_CNT_L, 0).unwrap();
+        assert_eq!(0x1234, ram.read_halfword(WAVE_RAM).unwrap());
+
+        // Turning the APU off clears the registers
+        ram.write_halfword(SOUND_CNT_X, 0).unwrap();
+        assert_eq!(0, ram.read_halfword(SOUND_2_CNT_L).unwrap());
+        assert_eq!(0, ram.read_halfword(SOUND_CNT_X).unwrap());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::utils::io_registers::TIMER_0_CNT_L;
+use crate::utils::Bitable;
+
+pub(super) const TIMERS: usize = 4;
+// Each timer has CNT_L and CNT_H, 4 bytes in total
+const TIMER_OFFSET: usize = 0x4;
+const TIMER_OVERFLOW: u32 = 0x10000;
+
+pub(super) fn timer_counter_address(timer: usize) -> usize {
+    TIMER_0_CNT_L + timer * TIMER_OFFSET
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct TimerControl {
+    pub prescaler: u32,
+    pub count_up: bool,
+    pub irq: bool,
+    pub enabled: bool,
+}
+
+impl From<u32> for TimerControl {
+    fn from(value: u32) -> Self {
+        TimerControl {
+            prescaler: value & 0b11,
+            count_up: value.bit_is_high(2),
+            irq: value.bit_is_high(6),
+            enabled: value.bit_is_high(7),
+        }
+    }
+}
+
+impl TimerControl {
+    /// Number of cycles for the timer to count up once
+    pub fn cycles_per_count(&self) -> u32 {
+        match self.prescaler {
+            0 => 1,
+            1 => 64,
+            2 => 256,
+            _ => 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(super) struct Timer {
+    pub counter: u32,
+    pub reload: u32,
+    pub control: TimerControl,
+    // Cycles that haven't added up to a full count yet
+    prescaler_cycles: u32,
+}
+
+impl Timer {
+    /// Adds `amount` to the counter, and returns the number of times the timer overflowed
+    fn increment(&mut self, amount: u32) -> u32 {
+        let mut overflows = 0;
+        let mut remaining = amount;
+
+        while remaining > 0 {
+            let until_overflow = TIMER_OVERFLOW - self.counter;
+            if remaining >= until_overflow {
+                remaining -= until_overflow;
+                self.counter = self.reload;
+                overflows += 1;
+            } else {
+                self.counter += remaining;
+                remaining = 0;
+            }
+        }
+
+        overflows
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(super) struct Timers {
+    pub timers: [Timer; TIMERS],
+    old_cycle: u32,
+}
+
+impl Timers {
+    pub fn write_reload(&mut self, timer: usize, value: u32) {
+        self.timers[timer].reload = value & 0xffff;
+    }
+
+    pub fn write_control(&mut self, timer: usize, value: u32) {
+        let control = TimerControl::from(value);
+        let t = &mut self.timers[timer];
+
+        // The reload value is copied into the counter when the timer is started
+        if control.enabled && !t.control.enabled {
+            t.counter = t.reload;
+            t.prescaler_cycles = 0;
+        }
+        t.control = control;
+    }
+
+    /// Runs the timers up to `cycle`, and returns the number of overflows for each timer
+    pub fn tick(&mut self, cycle: u32) -> [u32; TIMERS] {
+        let delta_cycle = cycle.wrapping_sub(self.old_cycle);
+        self.old_cycle = cycle;
+
+        let mut overflows = [0; TIMERS];
+        for i in 0..TIMERS {
+            let t = &mut self.timers[i];
+            if !t.control.enabled {
+                continue;
+            }
+
+            // NOTE: Count-Up does nothing for timer 0
+            let counts = if t.control.count_up && i > 0 {
+                overflows[i - 1]
+            } else {
+                let cycles = t.prescaler_cycles + delta_cycle;
+                let per_count = t.control.cycles_per_count();
+                t.prescaler_cycles = cycles % per_count;
+                cycles / per_count
+            };
+
+            overflows[i] = t.increment(counts);
+        }
+
+        overflows
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::Timers;
+
+    #[test]
+    fn timer_prescaler_64() {
+        let mut timers = Timers::default();
+        timers.write_reload(0, 0xfffe);
+        timers.write_control(0, 0x81);
+
+        assert_eq!([0, 0, 0, 0], timers.tick(63));
+        assert_eq!(0xfffe, timers.timers[0].counter);
+        assert_eq!([0, 0, 0, 0], timers.tick(64));
+        assert_eq!(0xffff, timers.timers[0].counter);
+        assert_eq!([1, 0, 0, 0], timers.tick(128));
+        assert_eq!(0xfffe, timers.timers[0].counter);
+    }
+
+    #[test]
+    fn timer_count_up_cascade() {
+        let mut timers = Timers::default();
+        timers.write_reload(0, 0xff00);
+        timers.write_control(0, 0x80);
+        timers.write_reload(1, 0xffff);
+        timers.write_control(1, 0x84);
+
+        assert_eq!([2, 2, 0, 0], timers.tick(0x200));
+        assert_eq!(0xff00, timers.timers[0].counter);
+        assert_eq!(0xffff, timers.timers[1].counter);
+    }
+
+    #[test]
+    fn timer_reload_only_on_enable() {
+        let mut timers = Timers::default();
+        timers.write_reload(2, 0x1000);
+        timers.write_control(2, 0x80);
+        timers.tick(0x10);
+        timers.write_reload(2, 0x2000);
+        timers.write_control(2, 0xc0);
+
+        assert_eq!(0x1010, timers.timers[2].counter);
+        assert!(timers.timers[2].control.irq);
+    }
+}
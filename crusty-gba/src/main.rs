@@ -6,13 +6,14 @@ mod utils;
 mod memory;
 
 use crate::ppu::Ppu;
-use crate::renderer::{run_debug, run_gui, run_ratatui};
+use crate::renderer::{run_debug, run_gui, run_headless, run_ratatui, KeyMapping, SaveFile};
 use clap::Parser;
 use cli::Args;
 use gba::cpu::Cpu;
 use gba::system::SystemMemory;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::Path;
 use tracing::{event, Level};
 use tracing_subscriber::filter::{LevelFilter, Targets};
 use tracing_subscriber::{fmt, prelude::*, reload};
@@ -48,21 +49,35 @@ fn main() -> Result<(), Error> {
         cpu.reset_cpu();
     }
 
-    let mut game_rom = File::open(args.game).expect("Unable to open GBA file");
+    let mut game_rom = File::open(&args.game).expect("Unable to open GBA file");
     memory.copy_game_pak(read_file_into_u32(&mut game_rom));
+    if let Some(chip) = args.flash_chip {
+        memory.set_flash_chip(chip);
+    }
+    let save_file = SaveFile::for_game(Path::new(&args.game));
+    save_file.load(&mut memory).expect("Unable to read save file");
 
-    let ppu = Ppu::default();
+    let mut ppu = Ppu::default();
+    ppu.set_color_correction(args.color_correction);
     // TODO: just use info!
     event!(Level::INFO, "Copied the stuff over");
 
     match args.render {
         cli::Renderer::Debug => run_debug(cpu, memory, ppu, reload_handle),
         cli::Renderer::Gui => {
-            let _ = run_gui(cpu, memory, reload_handle);
+            let mut key_mapping = KeyMapping::default();
+            for (button, key) in args.bindings {
+                key_mapping.bind(button, key);
+            }
+            let _ = run_gui(cpu, memory, ppu, key_mapping, save_file, reload_handle);
         }
         cli::Renderer::Ratatui => {
             let _ = run_ratatui();
         }
+        cli::Renderer::Headless => {
+            run_headless(cpu, memory, ppu, save_file, args.frames, args.wav.as_deref(), args.stems)
+                .expect("Unable to write WAV or save file");
+        }
     };
 
     Ok(())
@@ -9,6 +9,16 @@ pub trait Memory {
     fn write_word(&mut self, address: usize, block: u32) -> Result<(), MemoryError>;
     fn write_halfword(&mut self, address: usize, block: u32) -> Result<(), MemoryError>;
     fn write_byte(&mut self, address: usize, block: u32) -> Result<(), MemoryError>;
+
+    /// True when the CPU has been halted through HALTCNT
+    fn is_halted(&self) -> bool {
+        false
+    }
+
+    /// True when IME is set and an interrupt in IE has been requested in IF
+    fn interrupt_pending(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
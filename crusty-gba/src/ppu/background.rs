@@ -0,0 +1,320 @@
+use super::bg_control::{BgControl, BgOffset, BgRotScale};
+use super::{FRAME_BUFFER_1_OFFSET, WIDTH};
+
+// Each screen block is 32x32 tiles, 2 bytes per entry
+const SCREEN_BLOCK_SIZE: usize = 0x800;
+const CHARACTER_BLOCK_SIZE: usize = 0x4000;
+const SCREEN_BLOCK_PIXELS: usize = 256;
+
+pub(super) fn vram_byte(vram: &[u32], offset: usize) -> u32 {
+    vram[offset >> 2] >> ((offset & 0b11) * 8) & 0xff
+}
+
+pub(super) fn vram_halfword(vram: &[u32], offset: usize) -> u32 {
+    vram[offset >> 2] >> ((offset & 0b10) * 8) & 0xffff
+}
+
+/// Width and height in pixels of a text background
+fn text_screen_size(screen_size: u32) -> (usize, usize) {
+    match screen_size {
+        0 => (256, 256),
+        1 => (512, 256),
+        2 => (256, 512),
+        _ => (512, 512),
+    }
+}
+
+/// The color index of a pixel in a tile, 0 is always transparent. For 4bpp tiles
+/// the index is within the 16 color palette
+pub(super) fn tile_color_id(
+    vram: &[u32],
+    tile_address: usize,
+    x: usize,
+    y: usize,
+    is_256_color: bool,
+) -> usize {
+    if is_256_color {
+        vram_byte(vram, tile_address + y * 8 + x) as usize
+    } else {
+        let data = vram_byte(vram, tile_address + y * 4 + (x >> 1));
+        ((data >> ((x & 1) * 4)) & 0xf) as usize
+    }
+}
+
+/// Renders a single line of a text (mode 0/1) background as BGR555 colors.
+/// Transparent pixels are left as None
+pub(super) fn text_bg_line(
+    vram: &[u32],
+    palette: &[u32],
+    bg: &BgControl,
+    offset: &BgOffset,
+    y: usize,
+    line: &mut [Option<u32>; WIDTH],
+) {
+    let (width, height) = text_screen_size(bg.screen_size);
+    let map_y = (y + offset.y as usize) % height;
+    let tile_size = if bg.pallete { 64 } else { 32 };
+
+    for (x, pixel) in line.iter_mut().enumerate() {
+        let map_x = (x + offset.x as usize) % width;
+
+        // The 256x256 screen blocks are laid out left to right, then top to bottom
+        let screen_block = bg.screen_base_block
+            + (map_x / SCREEN_BLOCK_PIXELS)
+            + (map_y / SCREEN_BLOCK_PIXELS) * (width / SCREEN_BLOCK_PIXELS);
+        let tile_x = (map_x % SCREEN_BLOCK_PIXELS) / 8;
+        let tile_y = (map_y % SCREEN_BLOCK_PIXELS) / 8;
+        let entry = vram_halfword(
+            vram,
+            screen_block * SCREEN_BLOCK_SIZE + (tile_y * 32 + tile_x) * 2,
+        );
+
+        let tile_number = (entry & 0x3ff) as usize;
+        let h_flip = entry & 0x400 != 0;
+        let v_flip = entry & 0x800 != 0;
+        let palette_number = (entry >> 12) as usize;
+
+        let px = if h_flip { 7 - map_x % 8 } else { map_x % 8 };
+        let py = if v_flip { 7 - map_y % 8 } else { map_y % 8 };
+        let tile_address = bg.character_base_block * CHARACTER_BLOCK_SIZE + tile_number * tile_size;
+
+        let color_id = tile_color_id(vram, tile_address, px, py, bg.pallete);
+        *pixel = match color_id {
+            0 => None,
+            c if bg.pallete => Some(palette[c]),
+            c => Some(palette[palette_number * 16 + c]),
+        };
+    }
+}
+
+/// Horizontal mosaic, every pixel takes the color of the first pixel of its block
+pub(super) fn mosaic_line<T: Copy>(line: &mut [T; WIDTH], size: usize) {
+    for x in 0..WIDTH {
+        line[x] = line[x - x % size];
+    }
+}
+
+/// Steps through the texture coordinates of an affine line, `sample` gets the
+/// integer texture coordinates and returns the color there
+fn affine_line(
+    rot_scale: &BgRotScale,
+    reference: (i32, i32),
+    line: &mut [Option<u32>; WIDTH],
+    sample: impl Fn(i32, i32) -> Option<u32>,
+) {
+    let (mut tex_x, mut tex_y) = reference;
+    for pixel in line.iter_mut() {
+        *pixel = sample(tex_x >> 8, tex_y >> 8);
+        tex_x += rot_scale.dx;
+        tex_y += rot_scale.dy;
+    }
+}
+
+/// Renders a single line of an affine background, starting from the internal
+/// reference point for the line. Affine backgrounds are always 256 colors
+pub(super) fn affine_bg_line(
+    vram: &[u32],
+    palette: &[u32],
+    bg: &BgControl,
+    rot_scale: &BgRotScale,
+    reference: (i32, i32),
+    line: &mut [Option<u32>; WIDTH],
+) {
+    let size = 128 << bg.screen_size;
+
+    affine_line(rot_scale, reference, line, |x, y| {
+        let (x, y) = if bg.display_area_wraparound {
+            (x.rem_euclid(size), y.rem_euclid(size))
+        } else if (0..size).contains(&x) && (0..size).contains(&y) {
+            (x, y)
+        } else {
+            return None;
+        };
+        let (x, y) = (x as usize, y as usize);
+
+        // Each map entry is a single byte with just the tile number
+        let map_address = bg.screen_base_block * SCREEN_BLOCK_SIZE + (y / 8) * (size as usize / 8) + x / 8;
+        let tile_number = vram_byte(vram, map_address) as usize;
+        let tile_address = bg.character_base_block * CHARACTER_BLOCK_SIZE + tile_number * 64;
+
+        match tile_color_id(vram, tile_address, x % 8, y % 8, true) {
+            0 => None,
+            c => Some(palette[c]),
+        }
+    });
+}
+
+// BG Mode 3 (Single 240x160 direct color frame)
+//   06000000-06012BFF  75 KBytes Frame buffer
+// BG Mode 4,5 (Bitmap based Modes)
+//   06000000-06009FFF  40 KBytes Frame 0 buffer (only 37.5K used in Mode 4)
+//   0600A000-06013FFF  40 KBytes Frame 1 buffer (only 37.5K used in Mode 4)
+//   06014000-06017FFF  16 KBytes OBJ Tiles
+/// Renders a single line of BG2 in one of the bitmap modes. Bitmaps go through the
+/// affine path too, but never wrap around
+pub(super) fn bitmap_bg_line(
+    vram: &[u32],
+    palette: &[u32],
+    bg_mode: u32,
+    frame_select: bool,
+    rot_scale: &BgRotScale,
+    reference: (i32, i32),
+    line: &mut [Option<u32>; WIDTH],
+) {
+    let frame_start = if frame_select && bg_mode != 3 {
+        FRAME_BUFFER_1_OFFSET
+    } else {
+        0
+    };
+    let (width, height) = if bg_mode == 5 { (160, 128) } else { (240, 160) };
+
+    affine_line(rot_scale, reference, line, |x, y| {
+        if !(0..width).contains(&x) || !(0..height).contains(&y) {
+            return None;
+        }
+        let idx = (y * width + x) as usize;
+
+        match bg_mode {
+            4 => match vram_byte(vram, frame_start + idx) {
+                0 => None,
+                c => Some(palette[c as usize]),
+            },
+            _ => Some(vram_halfword(vram, frame_start + idx * 2) & 0x7fff),
+        }
+    });
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{
+        affine_bg_line, bitmap_bg_line, mosaic_line, text_bg_line, vram_byte, vram_halfword,
+    };
+    use crate::ppu::bg_control::{BgControl, BgOffset, BgRotScale};
+    use crate::ppu::WIDTH;
+
+    #[test]
+    fn vram_reads() {
+        let vram = vec![0x44332211, 0x88776655];
+        assert_eq!(0x33, vram_byte(&vram, 2));
+        assert_eq!(0x8877, vram_halfword(&vram, 6));
+    }
+
+    #[test]
+    fn text_bg_line_4bpp_flip_and_scroll() {
+        let mut vram = vec![0; 0x10000 / 4];
+        let mut palette = vec![0; 512];
+        // Palette 1, color 2
+        palette[18] = 0x1234;
+        // Screen block 1, tile 1 is drawn horizontally flipped with palette 1
+        vram[0x800 / 4] = 0x1401 << 16;
+        // Tile 1 in character block 1, the first pixel of row 3 is color 2
+        vram[(0x4000 + 32 + 3 * 4) / 4] = 0x2;
+
+        let bg = BgControl::from(0x0104);
+        let offset = BgOffset::from(3 << 16);
+        let mut line = [None; WIDTH];
+        text_bg_line(&vram, &palette, &bg, &offset, 0, &mut line);
+
+        assert_eq!(Some(0x1234), line[15]);
+        assert_eq!(None, line[8]);
+        assert_eq!(None, line[14]);
+    }
+
+    #[test]
+    fn text_bg_line_8bpp_wide_screen() {
+        let mut vram = vec![0; 0x10000 / 4];
+        let mut palette = vec![0; 512];
+        palette[0x10] = 0x7fff;
+        // The second screen block of a 512x256 map starts at x = 256
+        vram[0x800 / 4] = 0x2;
+        vram[64 * 2 / 4] = 0x10;
+
+        let bg = BgControl::from(0x4080);
+        let offset = BgOffset::from(250);
+        let mut line = [None; WIDTH];
+        text_bg_line(&vram, &palette, &bg, &offset, 0, &mut line);
+
+        assert_eq!(None, line[5]);
+        assert_eq!(Some(0x7fff), line[6]);
+    }
+
+    #[test]
+    fn affine_bg_line_scaled_and_wrapped() {
+        let mut vram = vec![0; 0x10000 / 4];
+        let mut palette = vec![0; 512];
+        palette[1] = 0x1f;
+        // Tile 1 is solid color 1, and is the last tile of the first row of the 128x128 map
+        vram[15 / 4] = 1 << 24;
+        for i in 0..16 {
+            vram[(64 + i * 4) / 4] = 0x01010101;
+        }
+
+        // Half size, starting at x = -8
+        let rot_scale = BgRotScale::from(&[0x0200, 0, 0, 0]);
+        let mut line = [None; WIDTH];
+        let bg = BgControl::from(0);
+        affine_bg_line(&vram, &palette, &bg, &rot_scale, (-8 << 8, 0), &mut line);
+        assert_eq!(None, line[0]);
+        assert_eq!(Some(0x1f), line[66]);
+        assert_eq!(None, line[150]);
+
+        let bg = BgControl::from(0x2000);
+        affine_bg_line(&vram, &palette, &bg, &rot_scale, (-8 << 8, 0), &mut line);
+        assert_eq!(Some(0x1f), line[0]);
+        assert_eq!(Some(0x1f), line[3]);
+        assert_eq!(None, line[4]);
+        assert_eq!(None, line[68]);
+    }
+
+    #[test]
+    fn bg_rot_scale_sign_extends() {
+        let rot_scale = BgRotScale::from(&[0xff00_0100, 0x0000_8000, 0x0fff_ff00, 0x0800_0000]);
+        assert_eq!(0x100, rot_scale.dx);
+        assert_eq!(-0x100, rot_scale.dmx);
+        assert_eq!(-0x8000, rot_scale.dy);
+        assert_eq!(-0x100, rot_scale.ref_x);
+        assert_eq!(-0x8000000, rot_scale.ref_y);
+    }
+
+    #[test]
+    fn bitmap_bg_line_modes() {
+        let mut vram = vec![0; 0x18000 / 4];
+        let mut palette = vec![0; 512];
+        palette[1] = 0x3e0;
+        let identity = BgRotScale::from(&[0x100, 0x100 << 16, 0, 0]);
+
+        // Mode 3, the second pixel of line 1
+        vram[(240 + 1) * 2 / 4] = 0x7c00 << 16;
+        let mut line = [None; WIDTH];
+        bitmap_bg_line(&vram, &palette, 3, true, &identity, (0, 1 << 8), &mut line);
+        assert_eq!(Some(0x7c00), line[1]);
+        assert_eq!(Some(0), line[0]);
+
+        // Mode 4 on the second frame, color 0 is transparent
+        vram[(0xa000 + 240) / 4] = 0x0100;
+        bitmap_bg_line(&vram, &palette, 4, true, &identity, (0, 1 << 8), &mut line);
+        assert_eq!(None, line[0]);
+        assert_eq!(Some(0x3e0), line[1]);
+
+        // Mode 5 is only 160 pixels wide
+        vram[0xa000 / 4] = 0x1f;
+        bitmap_bg_line(&vram, &palette, 5, true, &identity, (0, 0), &mut line);
+        assert_eq!(Some(0x1f), line[0]);
+        assert_eq!(Some(0), line[159]);
+        assert_eq!(None, line[160]);
+    }
+
+    #[test]
+    fn mosaic_line_repeats_first_pixel_of_block() {
+        let mut line = [None; WIDTH];
+        line[0] = Some(1);
+        line[4] = Some(2);
+        line[5] = Some(3);
+        mosaic_line(&mut line, 4);
+
+        assert_eq!([Some(1); 4], line[0..4]);
+        assert_eq!([Some(2); 4], line[4..8]);
+        // The last block is cut off by the edge of the screen
+        assert_eq!(None, line[WIDTH - 1]);
+    }
+}
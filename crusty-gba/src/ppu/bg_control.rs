@@ -1,4 +1,6 @@
-use crate::utils::io_registers::{BG_CONTROL0, BG_CONTROL1, BG_CONTROL2, BG_CONTROL3};
+use crate::utils::io_registers::{
+    BG0_X_OFFSET, BG2_DX, BG3_DX, BG_CONTROL0, BG_CONTROL1, BG_CONTROL2, BG_CONTROL3,
+};
 use crate::{
     gba::system::SystemMemory,
     memory::{MemoryError, Memory},
@@ -25,6 +27,22 @@ pub fn bg_control3(ram: &SystemMemory) -> Result<BgControl, MemoryError> {
     Ok(BgControl::from(data))
 }
 
+// BGxHOFS and BGxVOFS for each background are next to each other
+pub fn bg_offset(ram: &SystemMemory, bg: usize) -> Result<BgOffset, MemoryError> {
+    let data = ram.read_word(BG0_X_OFFSET + bg * 4)?;
+    Ok(BgOffset::from(data))
+}
+
+// Only BG2 and BG3 can be rotated and scaled
+pub fn bg_rot_scale(ram: &SystemMemory, bg: usize) -> Result<BgRotScale, MemoryError> {
+    let base = if bg == 2 { BG2_DX } else { BG3_DX };
+    let mut data = [0; 4];
+    for (i, word) in data.iter_mut().enumerate() {
+        *word = ram.read_word(base + i * 4)?;
+    }
+    Ok(BgRotScale::from(&data))
+}
+
 #[derive(Debug)]
 pub(super) struct BgControl {
     pub bg_priority: u32,
@@ -51,8 +69,8 @@ impl From<u32> for BgControl {
 }
 
 pub(super) struct BgOffset {
-    x: u32,
-    y: u32,
+    pub x: u32,
+    pub y: u32,
 }
 
 impl From<u32> for BgOffset {
@@ -64,24 +82,26 @@ impl From<u32> for BgOffset {
     }
 }
 
+/// dx, dmx, dy and dmy are PA, PB, PC and PD as signed 8.8 fixed point,
+/// the reference point is signed 20.8 fixed point
 pub(super) struct BgRotScale {
-    pub ref_x: u32,
-    pub ref_y: u32,
-    pub dx: u32,
-    pub dmx: u32,
-    pub dy: u32,
-    pub dmy: u32,
+    pub ref_x: i32,
+    pub ref_y: i32,
+    pub dx: i32,
+    pub dmx: i32,
+    pub dy: i32,
+    pub dmy: i32,
 }
 
 impl From<&[u32; 4]> for BgRotScale {
     fn from(value: &[u32; 4]) -> Self {
         BgRotScale {
-            dx: value[0].halfword_at(0),
-            dmx: value[0].halfword_at(16),
-            dy: value[1].halfword_at(0),
-            dmy: value[1].halfword_at(16),
-            ref_x: value[2] & 0xfffffff,
-            ref_y: value[3] & 0xfffffff,
+            dx: value[0].halfword_at(0) as i16 as i32,
+            dmx: value[0].halfword_at(16) as i16 as i32,
+            dy: value[1].halfword_at(0) as i16 as i32,
+            dmy: value[1].halfword_at(16) as i16 as i32,
+            ref_x: ((value[2] << 4) as i32) >> 4,
+            ref_y: ((value[3] << 4) as i32) >> 4,
         }
     }
 }
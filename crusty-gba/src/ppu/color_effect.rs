@@ -1,4 +1,23 @@
-use crate::utils::Bitable;
+use crate::utils::io_registers::{BLD_CNT, BRIGHTNESS};
+use crate::{
+    gba::system::SystemMemory,
+    memory::{MemoryError, Memory},
+    utils::Bitable,
+};
+
+pub fn color_effect_selection(ram: &SystemMemory) -> Result<ColorEffectSelection, MemoryError> {
+    let bld_cnt = ram.read_word(BLD_CNT)?;
+    let brightness = ram.read_halfword(BRIGHTNESS)?;
+    Ok(ColorEffectSelection::from((bld_cnt, brightness)))
+}
+
+/// A layer that can end up on top of a pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Layer {
+    Bg(usize),
+    Obj,
+    Backdrop,
+}
 
 pub(super) struct InternalColorEffect {
     pub bg0: bool,
@@ -22,6 +41,19 @@ impl From<u32> for InternalColorEffect {
     }
 }
 
+impl InternalColorEffect {
+    pub fn contains(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Bg(0) => self.bg0,
+            Layer::Bg(1) => self.bg1,
+            Layer::Bg(2) => self.bg2,
+            Layer::Bg(_) => self.bg3,
+            Layer::Obj => self.obj,
+            Layer::Backdrop => self.bd,
+        }
+    }
+}
+
 pub(super) enum ColorEffect {
     AlphaBlending { eva: EffectCoef, evb: EffectCoef },
     BrightnessIncrease(EffectCoef),
@@ -29,11 +61,12 @@ pub(super) enum ColorEffect {
 }
 
 // EffectCoef maxes out at 16
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(super) struct EffectCoef(u32);
 
 impl From<u32> for EffectCoef {
     fn from(value: u32) -> Self {
-        let x = value & 0b1111;
+        let x = value & 0b11111;
         if x > 16 {
             EffectCoef(16)
         } else {
@@ -46,24 +79,111 @@ pub(super) struct ColorEffectSelection {
     pub first_target: InternalColorEffect,
     pub second_target: InternalColorEffect,
     pub effect: Option<ColorEffect>,
+    // Semi-transparent OBJs always use alpha blending, even when BLDCNT picks another effect
+    pub eva: EffectCoef,
+    pub evb: EffectCoef,
 }
 
+// The first value is BLDCNT and BLDALPHA, the second is BLDY
 impl From<(u32, u32)> for ColorEffectSelection {
     fn from(value: (u32, u32)) -> Self {
+        let eva = EffectCoef::from(value.0 >> 16);
+        let evb = EffectCoef::from(value.0 >> 24);
         let effect = match (value.0 >> 6) & 0b11 {
             0 => None,
-            1 => Some(ColorEffect::AlphaBlending {
-                eva: EffectCoef::from(value.0 >> 15),
-                evb: EffectCoef::from(value.0 >> 23),
-            }),
-            2 => Some(ColorEffect::BrightnessIncrease(EffectCoef(value.1))),
-            3 => Some(ColorEffect::BrightnessDecrease(EffectCoef(value.1))),
+            1 => Some(ColorEffect::AlphaBlending { eva, evb }),
+            2 => Some(ColorEffect::BrightnessIncrease(EffectCoef::from(value.1))),
+            3 => Some(ColorEffect::BrightnessDecrease(EffectCoef::from(value.1))),
             _ => panic!(),
         };
         ColorEffectSelection {
-            first_target: InternalColorEffect::from(value.0 & 0x1f),
+            first_target: InternalColorEffect::from(value.0 & 0x3f),
             second_target: InternalColorEffect::from(value.0 >> 8),
             effect,
+            eva,
+            evb,
+        }
+    }
+}
+
+impl ColorEffectSelection {
+    /// The final BGR555 color of a pixel, given the top two layers. `semi_transparent` is
+    /// set when the top layer is a semi-transparent OBJ
+    pub fn apply(
+        &self,
+        (top_layer, top): (Layer, u32),
+        (bottom_layer, bottom): (Layer, u32),
+        semi_transparent: bool,
+    ) -> u32 {
+        let bottom_is_target = self.second_target.contains(bottom_layer);
+        if semi_transparent && bottom_is_target {
+            return alpha_blend(top, bottom, self.eva, self.evb);
+        }
+        if !self.first_target.contains(top_layer) {
+            return top;
+        }
+
+        match &self.effect {
+            Some(ColorEffect::AlphaBlending { eva, evb }) if bottom_is_target => {
+                alpha_blend(top, bottom, *eva, *evb)
+            }
+            Some(ColorEffect::BrightnessIncrease(evy)) => {
+                map_channels(top, |c| c + (((31 - c) * evy.0) >> 4))
+            }
+            Some(ColorEffect::BrightnessDecrease(evy)) => map_channels(top, |c| c - ((c * evy.0) >> 4)),
+            _ => top,
         }
     }
 }
+
+fn map_channels(color: u32, f: impl Fn(u32) -> u32) -> u32 {
+    (0..3).fold(0, |acc, i| acc | (f((color >> (i * 5)) & 0x1f) << (i * 5)))
+}
+
+fn alpha_blend(top: u32, bottom: u32, eva: EffectCoef, evb: EffectCoef) -> u32 {
+    (0..3).fold(0, |acc, i| {
+        let a = (top >> (i * 5)) & 0x1f;
+        let b = (bottom >> (i * 5)) & 0x1f;
+        acc | (((a * eva.0 + b * evb.0) >> 4).min(31) << (i * 5))
+    })
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{ColorEffectSelection, Layer};
+
+    #[test]
+    fn apply_alpha_blending() {
+        // BG0 on BG1, EVA 8 and EVB 12
+        let selection = ColorEffectSelection::from((0x0c08_0241, 0));
+        let top = (Layer::Bg(0), 0x001f);
+        let bottom = (Layer::Bg(1), 0x7c1f);
+
+        assert_eq!(0x5c1f, selection.apply(top, bottom, false));
+        // BG2 isn't a second target
+        assert_eq!(0x001f, selection.apply(top, (Layer::Bg(2), 0x7c1f), false));
+    }
+
+    #[test]
+    fn apply_brightness() {
+        // Backdrop only, EVY of 8
+        let increase = ColorEffectSelection::from((0x00a0, 8));
+        let decrease = ColorEffectSelection::from((0x00e0, 20));
+        let bottom = (Layer::Backdrop, 0);
+
+        assert_eq!(0x3def, increase.apply((Layer::Backdrop, 0), bottom, false));
+        assert_eq!(0x001f, increase.apply((Layer::Obj, 0x001f), bottom, false));
+        assert_eq!(0, decrease.apply((Layer::Backdrop, 0x7fff), bottom, false));
+    }
+
+    #[test]
+    fn apply_semi_transparent_obj() {
+        // Brightness increase on BG0, but BG1 is a second target with EVA and EVB at 16
+        let selection = ColorEffectSelection::from((0x1010_0281, 16));
+        let top = (Layer::Obj, 0x0010);
+        let bottom = (Layer::Bg(1), 0x0008);
+
+        assert_eq!(0x0018, selection.apply(top, bottom, true));
+        assert_eq!(0x0010, selection.apply(top, bottom, false));
+    }
+}
@@ -31,6 +31,7 @@ pub(super) struct DisplayControl {
     pub display_window0: bool,
     pub display_window1: bool,
     pub display_window_obj: bool,
+    pub green_swap: bool,
 }
 
 impl From<u32> for DisplayControl {
@@ -50,6 +51,7 @@ impl From<u32> for DisplayControl {
             display_window0: value.bit_is_high(13),
             display_window1: value.bit_is_high(14),
             display_window_obj: value.bit_is_high(15),
+            green_swap: value.bit_is_high(16),
         }
     }
 }
@@ -1,33 +1,53 @@
+mod background;
 mod bg_control;
 mod color_effect;
 mod disp_control;
 mod oam_attribute;
+mod objects;
+mod palette;
 mod window_control;
 
-use crate::utils::io_registers::{DISP_STAT, V_COUNT};
+pub use palette::ColorCorrection;
+
+use crate::gba::dma::DmaTrigger;
+use crate::gba::mapped_io::{IRQ_H_BLANK, IRQ_V_BLANK, IRQ_V_COUNTER};
+use crate::utils::io_registers::{DISP_STAT, MOSAIC, V_COUNT};
 use crate::{memory::{MemoryError, Memory}, utils::Bitable, SystemMemory};
-use bg_control::{bg_control0, bg_control1, bg_control2, bg_control3, BgControl};
-use disp_control::{display_control, DisplayControl};
+use background::{affine_bg_line, bitmap_bg_line, mosaic_line, text_bg_line};
+use bg_control::{
+    bg_control0, bg_control1, bg_control2, bg_control3, bg_offset, bg_rot_scale, BgControl, BgRotScale,
+};
+use disp_control::{display_control, display_stat, DisplayControl};
+use color_effect::{color_effect_selection, Layer};
+use window_control::Windows;
 use oam_attribute::{
-    get_bg_palettes, get_obj_palettes, Colors, OamAttribute, RotationScaleParameter,
-    RotationScaleParameterBuilder,
+    is_oam_entry_enabled, OamAttribute, RotationScaleParameter, RotationScaleParameterBuilder,
 };
-use tracing::{debug, error, info, trace, warn};
+use objects::obj_line;
+use palette::Palette;
+use tracing::{debug, info, trace, warn};
 // Base off of https://github.com/tuzz/game-loop
 
 const V_BLANK_FLAG: u32 = 0b00000001;
 const H_BLANK_FLAG: u32 = 0b00000010;
 const V_COUNTER_FLAG: u32 = 0b00000100;
 
-const BASE_OAM: u32 = 0x6010000;
+// Forced blank draws white
+const FORCED_BLANK_COLOR: u32 = 0x7fff;
+
 const HEIGHT: usize = 160;
 const WIDTH: usize = 240;
 
+// The PPU draws a dot every 4 cycles. A line is 308 dots, or 1232 cycles, and HBlank
+// starts after the 240 visible dots. VBlank is the last 68 of the 228 lines
+const DOTS_PER_LINE: u32 = 308;
+const LINES_PER_FRAME: u32 = 228;
+
 // Used for modes 3-5
 const FRAME_BUFFER_0_START: u32 = 0x6000000;
-const BITMAP_OBJ_DATA_OFFSET: u32 = 0x14000;
+const BITMAP_OBJ_DATA_OFFSET: usize = 0x14000;
 // Used for modes 4-5
-const FRAME_BUFFER_1_OFFSET: u32 = 0xA000;
+const FRAME_BUFFER_1_OFFSET: usize = 0xA000;
 
 fn set_bit_high(ram: &mut SystemMemory, addr: usize, flag: u32) {
     let io_ram = ram.get_io_ram();
@@ -38,7 +58,15 @@ fn set_bit_high(ram: &mut SystemMemory, addr: usize, flag: u32) {
 fn set_bit_low(ram: &mut SystemMemory, addr: usize, flag: u32) {
     let io_ram = ram.get_io_ram();
     let idx = (addr >> 2) & 0xffff;
-    io_ram[idx] |= !flag;
+    io_ram[idx] &= !flag;
+}
+
+// VCOUNT is readonly for the CPU, so it's updated directly in io ram
+fn set_v_count(ram: &mut SystemMemory, v_count: u32) {
+    let io_ram = ram.get_io_ram();
+    let idx = (V_COUNT >> 2) & 0xffff;
+    let shift = (V_COUNT & 0b11) * 8;
+    io_ram[idx] = (io_ram[idx] & !(0xffff << shift)) | ((v_count & 0xff) << shift);
 }
 
 #[derive(Debug)]
@@ -49,6 +77,9 @@ pub struct Ppu {
     // rename to frame_counter?
     frame: u32,
     next_frame: Vec<u8>,
+    // The internal reference points for BG2 and BG3
+    affine_references: [(i32, i32); 2],
+    palette: Palette,
 }
 
 impl Default for Ppu {
@@ -59,6 +90,8 @@ impl Default for Ppu {
             v_count: 0,
             frame: 0,
             next_frame: vec![255; HEIGHT * WIDTH * 4],
+            affine_references: [(0, 0); 2],
+            palette: Palette::new(ColorCorrection::default()),
         }
     }
 }
@@ -85,12 +118,28 @@ impl Ppu {
         d_cycle: u32,
         ram: &mut SystemMemory,
     ) -> Result<bool, MemoryError> {
-        let new_v = self.update_h_count(d_cycle, ram)?;
-        if new_v {
-            self.update_v_count(ram)
-        } else {
-            Ok(false)
+        let written = ram.take_affine_reference_writes();
+        for bg in [2, 3] {
+            if written & (1 << (bg - 2)) != 0 {
+                self.latch_affine_reference(ram, bg)?;
+            }
+        }
+        if ram.take_palette_write() {
+            self.palette.invalidate();
+        }
+
+        // Step to the next HBlank edge at most, so none of them get skipped
+        let mut d_cycle = d_cycle;
+        let mut frame_done = false;
+        while d_cycle > 0 {
+            let edge = if self.h_count < WIDTH as u32 { WIDTH as u32 } else { DOTS_PER_LINE };
+            let step = d_cycle.min(edge - self.h_count);
+            d_cycle -= step;
+            if self.update_h_count(step, ram)? {
+                frame_done |= self.update_v_count(ram)?;
+            }
         }
+        Ok(frame_done)
     }
 
     fn update_h_count(
@@ -101,13 +150,25 @@ impl Ppu {
         let next_h_count = self.h_count + d_cycle;
         trace!("setting h_blank to {}", next_h_count);
 
-        if self.h_count < 240 && next_h_count >= 240 {
+        if self.h_count < WIDTH as u32 && next_h_count >= WIDTH as u32 {
             self.h_count = next_h_count;
             debug!("Setting H_BLANK_FLAG hi");
             set_bit_high(ram, DISP_STAT, H_BLANK_FLAG);
+            // The HBlank IRQ happens on every line, even during VBlank
+            if display_stat(ram)?.h_blank_irq {
+                ram.request_interrupt(IRQ_H_BLANK);
+            }
+            // HBlank DMA only happens on the visible lines
+            if self.v_count < HEIGHT as u32 {
+                self.render_scanline(ram);
+                ram.trigger_dma(DmaTrigger::HBlank)?;
+            }
+            if (2..162).contains(&self.v_count) {
+                ram.trigger_dma(DmaTrigger::VideoCapture)?;
+            }
             Ok(false)
-        } else if self.h_count < 308 && next_h_count >= 308 {
-            self.h_count = next_h_count - 308;
+        } else if self.h_count < DOTS_PER_LINE && next_h_count >= DOTS_PER_LINE {
+            self.h_count = next_h_count - DOTS_PER_LINE;
             debug!("Setting H_BLANK_FLAG low");
             set_bit_low(ram, DISP_STAT, H_BLANK_FLAG);
             Ok(true)
@@ -121,152 +182,242 @@ impl Ppu {
     fn update_v_count(&mut self, ram: &mut SystemMemory) -> Result<bool, MemoryError> {
         self.v_count += 1;
         // TODO, well this propogate the error in set_bit_x?
-        if self.v_count == 160 {
+        if self.v_count == HEIGHT as u32 {
             debug!("Setting V_BLANK_FLAG hi");
             set_bit_high(ram, DISP_STAT, V_BLANK_FLAG);
-        } else if self.v_count == 226 {
+            self.latch_affine_reference(ram, 2)?;
+            self.latch_affine_reference(ram, 3)?;
+            ram.trigger_dma(DmaTrigger::VBlank)?;
+            if display_stat(ram)?.v_blank_irq {
+                ram.request_interrupt(IRQ_V_BLANK);
+            }
+        } else if self.v_count == 162 {
+            ram.stop_video_capture_dma()?;
+        } else if self.v_count == LINES_PER_FRAME - 1 {
+            // The VBlank flag is set on lines 160 to 226, but not the last line
             debug!("Setting V_BLANK_FLAG low");
             set_bit_low(ram, DISP_STAT, V_BLANK_FLAG);
-        } else if self.v_count == 228 {
+        } else if self.v_count == LINES_PER_FRAME {
             self.frame += 1;
             info!("Frame done {}", self.frame);
             self.v_count = 0;
         }
 
         debug!("Setting VCOUNT to {}", self.v_count);
-        if self.v_count == 0 {
-            ram.write_byte(V_COUNT, self.v_count).map(|_| true)
+        set_v_count(ram, self.v_count);
+        self.update_v_counter(ram)?;
+        Ok(self.v_count == 0)
+    }
+
+    /// Compares VCOUNT with the LYC setting in DISPSTAT
+    fn update_v_counter(&self, ram: &mut SystemMemory) -> Result<(), MemoryError> {
+        let stat = display_stat(ram)?;
+        if self.v_count == stat.v_count_setting {
+            set_bit_high(ram, DISP_STAT, V_COUNTER_FLAG);
+            if stat.v_counter_irq {
+                ram.request_interrupt(IRQ_V_COUNTER);
+            }
         } else {
-            ram.write_byte(V_COUNT, self.v_count).map(|_| false)
+            set_bit_low(ram, DISP_STAT, V_COUNTER_FLAG);
         }
+        Ok(())
     }
 
-    pub fn get_next_frame(&mut self, ram: &SystemMemory) -> Vec<u8> {
+    fn latch_affine_reference(&mut self, ram: &SystemMemory, bg: usize) -> Result<(), MemoryError> {
+        let rot_scale = bg_rot_scale(ram, bg)?;
+        self.affine_references[bg - 2] = (rot_scale.ref_x, rot_scale.ref_y);
+        Ok(())
+    }
+
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.palette.correction()
+    }
+
+    /// Changes how colors are converted to RGB, starting with the next line drawn
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.palette.set_correction(correction);
+    }
+
+    /// The frame buffer, which is drawn a line at a time as each line enters HBlank
+    pub fn get_next_frame(&self) -> Vec<u8> {
+        self.next_frame.clone()
+    }
+
+    /// Draws the current line with the registers as they are right now, so changes
+    /// made between lines show up on the screen
+    fn render_scanline(&mut self, ram: &SystemMemory) {
         let disp_control =
             display_control(ram).expect("Something went wrong grabbing the display control");
-        let bgs: Vec<BgControl> = match get_bgs(&disp_control, ram) {
-            Ok(b) => b,
-            Err(e) => {
-                panic!("Err occured: {}", e)
-            }
-        };
+        let y = self.v_count as usize;
 
-        info!(
-            "Display Control: {:?}, BGs enabled: {:?}",
-            disp_control, bgs
-        );
-        // OBJ stuff
-        let (objects, _) = if disp_control.display_obj {
-            get_objs_and_params(ram, &disp_control)
+        // NOTE: The CPU can always access VRAM, OAM and PAL RAM here, so forced blank
+        // only needs to blank the screen
+        let mut line = if disp_control.forced_blank {
+            [FORCED_BLANK_COLOR; WIDTH]
         } else {
-            (Vec::new(), Vec::new())
-        };
-
-        // TODO: Objs start later in VRAM when bg_mode is bitmap modes
-        let obj_palettes = get_obj_palettes(ram);
-        let bg_palettes = get_bg_palettes(ram);
-
-        // Do stuff with BGs here:
-        match disp_control.bg_mode {
-            2 => {
-                for obj in objects {
-                    let palette = obj_palettes.get_palette(obj.palette_idx);
-                    let tile_base = BASE_OAM + (obj.character_name * 32);
-
-                    // becuase each byte is 2 pixels
-                    //info!("Attemping to draw following OBJ: {:?}", obj);
-                    //info!("Using the following palette: {:?}", palette);
-                    for x in 0..(obj.obj_shape.h) {
-                        for y in 0..(obj.obj_shape.w) {
-                            // can probably progressively add stuff instead
-                            let (r, g, b) = if obj.is_256_color {
-                                let c_idx = get_color_id_256_colors_2d(x, y, tile_base, ram);
-                                if c_idx == 0 {
-                                    continue;
-                                }
-                                obj_palettes.get_256_color(c_idx)
-                            } else {
-                                let c_idx = get_color_id_16_palette_2d(x, y, tile_base, ram);
-                                palette[c_idx]
-                            };
-
-                            let buffer_idx = euclid_to_buffer_idx(
-                                (x + obj.x_coord) as usize,
-                                (y + obj.y_coord) as usize,
-                            );
-                            if buffer_idx + 2 <= self.next_frame.len() {
-                                self.next_frame[buffer_idx] = r;
-                                self.next_frame[buffer_idx + 1] = g;
-                                self.next_frame[buffer_idx + 2] = b;
-                            }
-                        }
-                    }
+            let bgs: Vec<(usize, BgControl)> = match get_bgs(&disp_control, ram) {
+                Ok(b) => b,
+                Err(e) => {
+                    panic!("Err occured: {}", e)
                 }
+            };
+
+            trace!(
+                "Display Control: {:?}, BGs enabled: {:?}",
+                disp_control, bgs
+            );
+            // OBJ stuff
+            let (objects, params) = if disp_control.display_obj {
+                get_objs_and_params(ram, &disp_control)
+            } else {
+                (Vec::new(), Vec::new())
+            };
+
+            if disp_control.bg_mode > 5 {
+                // NOTE: Modes 6 and 7 are prohibited, they have no BGs so only the
+                // backdrop and objects are drawn
+                warn!("Prohibited background mode {}", disp_control.bg_mode);
             }
-            4 => display_mode_4(ram, &disp_control, bg_palettes, &mut self.next_frame),
-            _ => {
-                error!("{:?}", disp_control.bg_mode);
-                todo!();
-            }
+            self.render_line(ram, &disp_control, &bgs, &objects, &params, y)
+        };
+        if disp_control.green_swap {
+            swap_green(&mut line);
         }
 
-        self.next_frame.clone()
+        // The internal reference points move by PB and PD after every line
+        for (i, reference) in self.affine_references.iter_mut().enumerate() {
+            let rot_scale = bg_rot_scale(ram, i + 2).expect("Unable to read BG rotation");
+            reference.0 += rot_scale.dmx;
+            reference.1 += rot_scale.dmy;
+        }
+
+        for (x, color) in line.into_iter().enumerate() {
+            let [r, g, b] = self.palette.rgb(color);
+            let buffer_idx = euclid_to_buffer_idx(x, y);
+            self.next_frame[buffer_idx] = r;
+            self.next_frame[buffer_idx + 1] = g;
+            self.next_frame[buffer_idx + 2] = b;
+        }
     }
-}
 
-// BG Mode 4,5 (Bitmap based Modes)
-//   06000000-06009FFF  40 KBytes Frame 0 buffer (only 37.5K used in Mode 4)
-//   0600A000-06013FFF  40 KBytes Frame 1 buffer (only 37.5K used in Mode 4)
-//   06014000-06017FFF  16 KBytes OBJ Tiles
-fn display_mode_4(
-    ram: &SystemMemory,
-    disp_control: &DisplayControl,
-    palette: Colors,
-    pixels: &mut Vec<u8>,
-) {
-    let start_idx = if !disp_control.display_frame_select {
-        0
-    } else {
-        0xa000
-    };
+    fn render_line(
+        &mut self,
+        ram: &SystemMemory,
+        disp_control: &DisplayControl,
+        bgs: &[(usize, BgControl)],
+        objects: &[OamAttribute],
+        params: &[RotationScaleParameter],
+        y: usize,
+    ) -> [u32; WIDTH] {
+        let vram = ram.get_vram();
+        let palette = self.palette.colors(ram.get_palette_ram_slice());
+
+        // Lower priority values are drawn on top, and lower BGs win ties
+        let mut layers: Vec<&(usize, BgControl)> = bgs.iter().collect();
+        layers.sort_by_key(|(idx, bg)| (bg.bg_priority, *idx));
+
+        let mosaic = mosaic(ram).expect("Unable to read the mosaic size");
+        let mut bg_lines = Vec::new();
+        for (idx, bg) in layers {
+            // Vertical mosaic repeats the first line of each block
+            let mosaic_lines = if bg.mosaic { y % mosaic.bg_v } else { 0 };
+            let mut bg_line = [None; WIDTH];
+            match (disp_control.bg_mode, *idx) {
+                (0, _) | (1, 0 | 1) => {
+                    let offset = bg_offset(ram, *idx).expect("Unable to read BG offset");
+                    text_bg_line(vram, palette, bg, &offset, y - mosaic_lines, &mut bg_line);
+                }
+                (1 | 2, 2 | 3) => {
+                    let rot_scale = bg_rot_scale(ram, *idx).expect("Unable to read BG rotation");
+                    let reference =
+                        mosaic_reference(self.affine_references[*idx - 2], &rot_scale, mosaic_lines);
+                    affine_bg_line(vram, palette, bg, &rot_scale, reference, &mut bg_line);
+                }
+                (3..=5, 2) => {
+                    let rot_scale = bg_rot_scale(ram, 2).expect("Unable to read BG rotation");
+                    bitmap_bg_line(
+                        vram,
+                        palette,
+                        disp_control.bg_mode,
+                        disp_control.display_frame_select,
+                        &rot_scale,
+                        mosaic_reference(self.affine_references[0], &rot_scale, mosaic_lines),
+                        &mut bg_line,
+                    );
+                }
+                _ => {}
+            }
+            if bg.mosaic {
+                mosaic_line(&mut bg_line, mosaic.bg_h);
+            }
+            bg_lines.push((*idx, bg.bg_priority, bg_line));
+        }
 
-    for i in 0..(WIDTH * HEIGHT) {
-        let byte_idx = start_idx + 0x6000000 + i;
-        let data = match ram.read_byte(byte_idx) {
-            Ok(x) => x,
-            Err(e) => {
-                error!("Unable to read byte from: {byte_idx} because: {e}");
-                panic!()
+        let obj_line = obj_line(vram, palette, objects, params, disp_control, &mosaic, y);
+        let windows = Windows::new(ram, disp_control).expect("Unable to read the windows");
+        let effects = color_effect_selection(ram).expect("Unable to read the color effects");
+
+        // The backdrop is the first color of the BG palette
+        let backdrop = palette[0];
+        let mut line = [backdrop; WIDTH];
+        for (x, pixel) in line.iter_mut().enumerate() {
+            let window = windows.at(x, y, obj_line.window[x]);
+            let obj = obj_line.pixels[x].filter(|_| window.obj);
+
+            // Sprites are drawn over BGs with the same priority, and the backdrop is under
+            // everything. Only the top two layers matter for the color effects
+            let mut top_two = [(Layer::Backdrop, backdrop); 2];
+            let mut found = 0;
+            let mut obj_pending = obj;
+            for (idx, priority, bg_line) in bg_lines.iter().filter(|(idx, _, _)| window.bg(*idx)) {
+                let Some(color) = bg_line[x] else {
+                    continue;
+                };
+                if let Some(o) = obj_pending.filter(|o| o.priority <= *priority) {
+                    top_two[found] = (Layer::Obj, o.color);
+                    obj_pending = None;
+                    found += 1;
+                    if found == 2 {
+                        break;
+                    }
+                }
+                top_two[found] = (Layer::Bg(*idx), color);
+                found += 1;
+                if found == 2 {
+                    break;
+                }
             }
-        };
+            if let Some(o) = obj_pending.filter(|_| found < 2) {
+                top_two[found] = (Layer::Obj, o.color);
+            }
+
+            let semi_transparent = top_two[0].0 == Layer::Obj && obj.is_some_and(|o| o.semi_transparent);
+            *pixel = if window.color_special {
+                effects.apply(top_two[0], top_two[1], semi_transparent)
+            } else {
+                top_two[0].1
+            };
+        }
 
-        let (r, g, b) = palette.get_256_color(data as usize);
-        let buffer_idx = i * 4;
-        pixels[buffer_idx] = r;
-        pixels[buffer_idx + 1] = g;
-        pixels[buffer_idx + 2] = b;
+        line
     }
 }
 
-fn get_color_id_16_palette_2d(x: u32, y: u32, tile_base: u32, ram: &SystemMemory) -> usize {
-    // Translating
-    let idx = tile_base + ((x % 8) >> 1) + ((x >> 3) * 0x40) + (0x4 * (y % 8)) + (0x400 * (y >> 3));
-    let pixel_byte = ram
-        .read_byte(idx as usize)
-        .expect("Error reading byte while writing to pixel buffer");
-    if x & 1 == 0 {
-        (pixel_byte & 0xf) as usize
-    } else {
-        ((pixel_byte >> 4) & 0xf) as usize
+/// The undocumented green swap, each pair of pixels trade their green channels
+fn swap_green(line: &mut [u32; WIDTH]) {
+    const GREEN: u32 = 0x1f << 5;
+    for pair in line.chunks_exact_mut(2) {
+        let (left, right) = (pair[0], pair[1]);
+        pair[0] = (left & !GREEN) | (right & GREEN);
+        pair[1] = (right & !GREEN) | (left & GREEN);
     }
 }
 
-fn get_color_id_256_colors_2d(x: u32, y: u32, tile_base: u32, ram: &SystemMemory) -> usize {
-    let idx = tile_base + x % 8 + ((x >> 3) * 0x40) + (0x8 * (y % 8)) + (0x400 * (y >> 3));
-    let pixel_byte = ram
-        .read_byte(idx as usize)
-        .expect("Error reading byte while writing to pixel buffer");
-    pixel_byte as usize
+/// The internal reference point of the first line of a vertical mosaic block,
+/// `lines` lines above the current one
+fn mosaic_reference(reference: (i32, i32), rot_scale: &BgRotScale, lines: usize) -> (i32, i32) {
+    let lines = lines as i32;
+    (reference.0 - rot_scale.dmx * lines, reference.1 - rot_scale.dmy * lines)
 }
 
 fn euclid_to_buffer_idx(x: usize, y: usize) -> usize {
@@ -277,54 +428,33 @@ fn euclid_to_buffer_idx(x: usize, y: usize) -> usize {
 fn get_bgs(
     disp_control: &DisplayControl,
     ram: &SystemMemory,
-) -> Result<Vec<BgControl>, MemoryError> {
-    let mut bgs: Vec<BgControl> = Vec::new();
+) -> Result<Vec<(usize, BgControl)>, MemoryError> {
+    let displayed = [
+        disp_control.display_bg0,
+        disp_control.display_bg1,
+        disp_control.display_bg2,
+        disp_control.display_bg3,
+    ];
+
+    let available = match disp_control.bg_mode {
+        0 => 0..4,
+        1 => 0..3,
+        2 => 2..4,
+        // NOTE: If I'm reading the docs right, Layer 2 is used,
+        // but bg_control2 is not required
+        3..=5 => 2..3,
+        _ => 0..0,
+    };
 
-    match disp_control.bg_mode {
-        0 => {
-            if disp_control.display_bg0 {
-                bgs.push(bg_control0(ram)?);
-            }
-            if disp_control.display_bg1 {
-                bgs.push(bg_control1(ram)?);
-            }
-            if disp_control.display_bg2 {
-                bgs.push(bg_control2(ram)?);
-            }
-            if disp_control.display_bg3 {
-                bgs.push(bg_control3(ram)?);
-            }
-        }
-        1 => {
-            if disp_control.display_bg0 {
-                bgs.push(bg_control0(ram)?);
-            }
-            if disp_control.display_bg1 {
-                bgs.push(bg_control1(ram)?);
-            }
-            if disp_control.display_bg2 {
-                bgs.push(bg_control2(ram)?);
-            }
-        }
-        2 => {
-            if disp_control.display_bg2 {
-                bgs.push(bg_control2(ram)?);
-            }
-            if disp_control.display_bg3 {
-                bgs.push(bg_control3(ram)?);
-            }
-        }
-        3..=5 => {
-            // NOTE: If I'm reading the docs right, Layer 2 is used,
-            // but bg_control2 is not required
-            if disp_control.display_bg2 {
-                bgs.push(bg_control2(ram)?);
-            }
-        }
-        _ => {
-            error!("Background Mode cannot be more than 5");
-            panic!()
-        }
+    let mut bgs = Vec::new();
+    for bg in available.filter(|bg| displayed[*bg]) {
+        let control = match bg {
+            0 => bg_control0(ram)?,
+            1 => bg_control1(ram)?,
+            2 => bg_control2(ram)?,
+            _ => bg_control3(ram)?,
+        };
+        bgs.push((bg, control));
     }
     Ok(bgs)
 }
@@ -340,7 +470,7 @@ fn get_objs_and_params(
     let mut param_builder = RotationScaleParameterBuilder::new();
 
     for chunk in oam.chunks(2) {
-        if chunk[0] != 0 && (chunk[1] & 0xffff) != 0 {
+        if is_oam_entry_enabled(chunk) {
             objs.push(OamAttribute::from(chunk));
         }
         param_builder.add_parameter(chunk[1]);
@@ -350,20 +480,118 @@ fn get_objs_and_params(
     (objs, params)
 }
 
+fn mosaic(ram: &SystemMemory) -> Result<Mosaic, MemoryError> {
+    Ok(Mosaic::from(ram.read_halfword(MOSAIC)?))
+}
+
+/// The size of the mosaic blocks in pixels, the register holds the size minus 1
+#[derive(Debug, Clone, Copy, PartialEq)]
 struct Mosaic {
-    bg_h: i32,
-    bg_v: i32,
-    obj_h: i32,
-    obj_v: i32,
+    bg_h: usize,
+    bg_v: usize,
+    obj_h: usize,
+    obj_v: usize,
 }
 
 impl From<u32> for Mosaic {
     fn from(value: u32) -> Self {
         Mosaic {
-            bg_h: (value.half_byte_at(0) as i32) - 1,
-            bg_v: (value.half_byte_at(4) as i32) - 1,
-            obj_h: (value.half_byte_at(8) as i32) - 1,
-            obj_v: (value.half_byte_at(12) as i32) - 1,
+            bg_h: value.half_byte_at(0) as usize + 1,
+            bg_v: value.half_byte_at(4) as usize + 1,
+            obj_h: value.half_byte_at(8) as usize + 1,
+            obj_v: value.half_byte_at(12) as usize + 1,
         }
     }
 }
+
+mod test {
+    #![allow(unused)]
+    use super::{euclid_to_buffer_idx, swap_green, Ppu, WIDTH};
+    use crate::memory::Memory;
+    use crate::SystemMemory;
+
+    #[test]
+    fn tick_renders_each_line_at_h_blank() {
+        let mut ram = SystemMemory::new();
+        let mut ppu = Ppu::default();
+        // The backdrop is changed between lines 0 and 1
+        ram.write_halfword(0x5000000, 0x001f).unwrap();
+        ppu.tick(240 * 4, &mut ram);
+        ram.write_halfword(0x5000000, 0x7c00).unwrap();
+        ppu.tick(308 * 4, &mut ram);
+        ppu.tick((308 + 240) * 4, &mut ram);
+
+        let frame = ppu.get_next_frame();
+        let first = euclid_to_buffer_idx(0, 0);
+        let second = euclid_to_buffer_idx(0, 1);
+        assert_eq!([255, 0, 0], frame[first..first + 3]);
+        assert_eq!([0, 0, 255], frame[second..second + 3]);
+    }
+
+    #[test]
+    fn tick_updates_display_stat_and_requests_interrupts() {
+        let mut ram = SystemMemory::new();
+        let mut ppu = Ppu::default();
+        // All of the IRQs, with a VCOUNT setting of 5
+        ram.write_halfword(0x4000004, 0x0538).unwrap();
+
+        ppu.tick(240 * 4, &mut ram);
+        assert_eq!(0x0002, ram.read_halfword(0x4000202).unwrap());
+        assert_eq!(0x0002, ram.read_halfword(0x4000004).unwrap() & 0x7);
+
+        // Line 5 matches, and HBlank is over
+        ram.write_halfword(0x4000202, 0x0002).unwrap();
+        ppu.tick(5 * 1232, &mut ram);
+        assert_eq!(0x0006, ram.read_halfword(0x4000202).unwrap());
+        assert_eq!(0x0004, ram.read_halfword(0x4000004).unwrap() & 0x7);
+
+        // The match flag is cleared on the next line, VBlank starts at line 160
+        ram.write_halfword(0x4000202, 0x0006).unwrap();
+        ppu.tick(160 * 1232, &mut ram);
+        assert_eq!(0x0003, ram.read_halfword(0x4000202).unwrap());
+        assert_eq!(0x0001, ram.read_halfword(0x4000004).unwrap() & 0x7);
+
+        // VBlank is cleared on the last line of the frame
+        ppu.tick(227 * 1232, &mut ram);
+        assert_eq!(0x0000, ram.read_halfword(0x4000004).unwrap() & 0x7);
+        assert!(ppu.tick(228 * 1232, &mut ram));
+    }
+
+    #[test]
+    fn tick_draws_white_in_forced_blank() {
+        let mut ram = SystemMemory::new();
+        let mut ppu = Ppu::default();
+        // Forced blank, the CGB mode bit can't be set
+        ram.write_halfword(0x4000000, 0x0088).unwrap();
+        assert_eq!(0x0080, ram.read_halfword(0x4000000).unwrap());
+        ppu.tick(240 * 4, &mut ram);
+
+        let frame = ppu.get_next_frame();
+        let idx = euclid_to_buffer_idx(100, 0);
+        assert_eq!([255, 255, 255], frame[idx..idx + 3]);
+    }
+
+    #[test]
+    fn tick_draws_the_backdrop_in_prohibited_modes() {
+        let mut ram = SystemMemory::new();
+        let mut ppu = Ppu::default();
+        // Mode 7 with every BG enabled
+        ram.write_halfword(0x4000000, 0x0f07).unwrap();
+        ram.write_halfword(0x5000000, 0x001f).unwrap();
+        ppu.tick(240 * 4, &mut ram);
+
+        let frame = ppu.get_next_frame();
+        let idx = euclid_to_buffer_idx(100, 0);
+        assert_eq!([255, 0, 0], frame[idx..idx + 3]);
+    }
+
+    #[test]
+    fn swap_green_trades_between_pairs() {
+        let mut line = [0; WIDTH];
+        line[0] = 0x7fff;
+        line[3] = 0x03e0;
+        swap_green(&mut line);
+
+        assert_eq!([0x7c1f, 0x03e0, 0x03e0, 0], line[0..4]);
+    }
+}
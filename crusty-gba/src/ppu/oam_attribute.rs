@@ -1,26 +1,12 @@
-use crate::{
-    gba::system::SystemMemory,
-    utils::{Bitable, BittableColor},
-};
+use crate::utils::Bitable;
 
 const ROT_SCALE_FLAG: u32 = 0x100;
 const OBJECT_FLAG: u32 = 0x200;
-const BASE_OBJ_PALETTE: usize = 0x200 / 4;
 
 pub fn is_oam_entry_enabled(value: &[u32]) -> bool {
     (value[0] >> 8 & 0b11) != 0b10
 }
 
-pub fn get_obj_palettes(ram: &SystemMemory) -> Colors {
-    let palette_ram = ram.get_palette_ram_slice();
-    Colors::from(&palette_ram[BASE_OBJ_PALETTE..BASE_OBJ_PALETTE + (512 / 4)])
-}
-
-pub fn get_bg_palettes(ram: &SystemMemory) -> Colors {
-    let palette_ram = ram.get_palette_ram_slice();
-    Colors::from(&palette_ram[0..512 / 4])
-}
-
 #[derive(Debug)]
 pub struct OamAttribute {
     pub y_coord: u32,
@@ -71,13 +57,13 @@ impl From<&[u32]> for Transformation {
     fn from(value: &[u32]) -> Self {
         let rot = value[0] & ROT_SCALE_FLAG == ROT_SCALE_FLAG;
         if rot {
-            let rot_scale_param = (value[0] >> 24) & 0x1f;
+            let rot_scale_param = (value[0] >> 25) & 0x1f;
             Transformation::RotScale {
                 idx: rot_scale_param as usize,
             }
         } else {
-            let horizontal = (value[0] >> 27) == 0x1;
-            let veritical = (value[0] >> 28) == 0x1;
+            let horizontal = value[0].bit_is_high(28);
+            let veritical = value[0].bit_is_high(29);
             Transformation::Flip {
                 horizontal,
                 veritical,
@@ -90,7 +76,8 @@ impl From<&[u32]> for OamAttribute {
     fn from(value: &[u32]) -> Self {
         let obj_shape = (value[0] >> 14) & 0b11;
         let obj_size = (value[0] >> 30) & 0b11;
-        let secondary_size = [8, 8, 16, 32];
+        let wide_size = [16, 32, 32, 64];
+        let narrow_size = [8, 8, 16, 32];
 
         let base_size = 8 * (1 << obj_size);
         let shape = match obj_shape {
@@ -99,19 +86,20 @@ impl From<&[u32]> for OamAttribute {
                 h: base_size,
             },
             1 => Shape {
-                w: base_size,
-                h: secondary_size[obj_size as usize],
+                w: wide_size[obj_size as usize],
+                h: narrow_size[obj_size as usize],
             },
             2 => Shape {
-                w: secondary_size[obj_size as usize],
-                h: base_size,
+                w: narrow_size[obj_size as usize],
+                h: wide_size[obj_size as usize],
             },
-            _ => panic!(),
+            // NOTE: Shape 3 is prohibited, treating it as the smallest sprite
+            _ => Shape { w: 8, h: 8 },
         };
 
         Self {
             y_coord: value[0] & 0xff,
-            x_coord: value[1] & 0x1ff,
+            x_coord: (value[0] >> 16) & 0x1ff,
             is_rot_scale: value[0].bit_is_high(8),
             object_flag: ObjectFlag::from(value),
             obj_shape: shape,
@@ -121,22 +109,23 @@ impl From<&[u32]> for OamAttribute {
             transformation: Transformation::from(value),
             character_name: value[1] & 0x3ff,
             priority: (value[1] >> 10) & 0b11,
-            palette_idx: ((value[1] >> 12) & 0b111) as usize,
+            palette_idx: ((value[1] >> 12) & 0xf) as usize,
         }
     }
 }
 
 // TODO: Back port this to BgRotScale?
+/// PA, PB, PC and PD as signed 8.8 fixed point
 #[derive(Debug)]
 pub struct RotationScaleParameter {
-    pub dx: u32,
-    pub dmx: u32,
-    pub dy: u32,
-    pub dmy: u32,
+    pub dx: i32,
+    pub dmx: i32,
+    pub dy: i32,
+    pub dmy: i32,
 }
 
 pub struct RotationScaleParameterBuilder {
-    parameters: Vec<u32>,
+    parameters: Vec<i32>,
 }
 
 impl RotationScaleParameterBuilder {
@@ -147,7 +136,7 @@ impl RotationScaleParameterBuilder {
     }
 
     pub fn add_parameter(&mut self, param: u32) {
-        self.parameters.push((param >> 16) & 0xffff);
+        self.parameters.push((param >> 16) as i16 as i32);
     }
 
     pub fn build(&self) -> Vec<RotationScaleParameter> {
@@ -165,51 +154,3 @@ impl RotationScaleParameterBuilder {
         res
     }
 }
-
-// I could save everything into a palette and when i need to grab
-// a color as if it's a 256/1 through a mapping function
-pub struct Colors {
-    palettes: Vec<Palette>,
-}
-
-pub struct Palette {
-    colors: Vec<(u8, u8, u8)>,
-}
-
-impl Colors {
-    pub fn get_palette(&self, palette_id: usize) -> &Vec<(u8, u8, u8)> {
-        &self.palettes[palette_id].colors
-    }
-
-    pub fn get_256_color(&self, idx: usize) -> (u8, u8, u8) {
-        self.palettes[idx / 16].colors[idx % 16]
-    }
-
-    pub fn num_of_palettes(&self) -> usize {
-        self.palettes.len()
-    }
-}
-
-impl From<&[u32]> for Colors {
-    // TODO: This will need some heavy refactors
-    fn from(value: &[u32]) -> Self {
-        let mut palettes: Vec<Palette> = Vec::new();
-        let mut colors: Vec<(u8, u8, u8)> = Vec::new();
-
-        for x in value.chunks(8) {
-            let mut pal_colors = Vec::new();
-
-            for i in x {
-                let (c1, c2) = i.to_8bit_color();
-                pal_colors.push(c1);
-                pal_colors.push(c2);
-                colors.push(c1);
-                colors.push(c2);
-            }
-
-            palettes.push(Palette { colors: pal_colors });
-        }
-
-        Self { palettes }
-    }
-}
@@ -0,0 +1,328 @@
+use super::background::tile_color_id;
+use super::disp_control::DisplayControl;
+use super::oam_attribute::{ObjectFlag, OamAttribute, RotationScaleParameter, Transformation};
+use super::{Mosaic, BITMAP_OBJ_DATA_OFFSET, WIDTH};
+
+// OBJ tiles start at 0x6010000, and the OBJ palette is after the 256 BG colors
+const OBJ_VRAM_OFFSET: usize = 0x10000;
+const OBJ_VRAM_SIZE: usize = 0x8000;
+const OBJ_PALETTE_OFFSET: usize = 256;
+
+// Cycles the PPU can spend on sprites each line
+const OBJ_CYCLES_PER_LINE: u32 = 1210;
+const OBJ_CYCLES_PER_LINE_H_BLANK_FREE: u32 = 954;
+
+const OBJ_MODE_SEMI_TRANSPARENT: u32 = 1;
+const OBJ_MODE_WINDOW: u32 = 2;
+
+/// The sprites on a single line
+pub(super) struct ObjLine {
+    pub pixels: [Option<ObjPixel>; WIDTH],
+    /// Set for the pixels inside the OBJ window
+    pub window: [bool; WIDTH],
+}
+
+/// A visible sprite pixel on the current line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct ObjPixel {
+    pub color: u32,
+    pub priority: u32,
+    pub semi_transparent: bool,
+}
+
+/// X is 9 bits, so anything past 255 is off the left side of the screen
+fn obj_x(obj: &OamAttribute) -> i32 {
+    if obj.x_coord >= 256 {
+        obj.x_coord as i32 - 512
+    } else {
+        obj.x_coord as i32
+    }
+}
+
+/// The line within the sprite, or None if the sprite isn't on line `y`. Y is 8 bits,
+/// so sprites near the bottom wrap around to the top
+fn obj_row(obj: &OamAttribute, y: usize, height: u32) -> Option<u32> {
+    let row = (y as u32).wrapping_sub(obj.y_coord) & 0xff;
+    if row < height {
+        Some(row)
+    } else {
+        None
+    }
+}
+
+/// The color index of a pixel in the sprite, using the texture coordinates within the sprite
+fn obj_color_id(
+    vram: &[u32],
+    obj: &OamAttribute,
+    disp_control: &DisplayControl,
+    x: u32,
+    y: u32,
+) -> usize {
+    // 256 color tiles take up 2 tile numbers
+    let tile_step = if obj.is_256_color { 2 } else { 1 };
+    let (tile_x, tile_y) = (x / 8, y / 8);
+    let row_step = if disp_control.obj_character_mapping {
+        (obj.obj_shape.w / 8) * tile_step
+    } else {
+        32
+    };
+    let tile_number = obj.character_name + tile_y * row_step + tile_x * tile_step;
+    let tile_address = OBJ_VRAM_OFFSET + ((tile_number as usize * 32) % OBJ_VRAM_SIZE);
+
+    // The bitmap modes use the first half of OBJ VRAM for the frame buffers
+    if disp_control.bg_mode >= 3 && tile_address < BITMAP_OBJ_DATA_OFFSET {
+        return 0;
+    }
+
+    tile_color_id(vram, tile_address, (x % 8) as usize, (y % 8) as usize, obj.is_256_color)
+}
+
+/// Maps a pixel in the bounding box of an affine sprite back to the sprite's texture.
+/// The matrix is applied around the center of the box and the sprite
+fn affine_texel(
+    param: &RotationScaleParameter,
+    col: u32,
+    row: u32,
+    (box_w, box_h): (u32, u32),
+    (width, height): (u32, u32),
+) -> Option<(u32, u32)> {
+    let dx = col as i32 - (box_w / 2) as i32;
+    let dy = row as i32 - (box_h / 2) as i32;
+    let tex_x = ((param.dx * dx + param.dmx * dy) >> 8) + (width / 2) as i32;
+    let tex_y = ((param.dy * dx + param.dmy * dy) >> 8) + (height / 2) as i32;
+
+    if (0..width as i32).contains(&tex_x) && (0..height as i32).contains(&tex_y) {
+        Some((tex_x as u32, tex_y as u32))
+    } else {
+        None
+    }
+}
+
+/// Renders all of the sprites on line `y`. Sprites with a lower priority value are on top,
+/// then the sprite that comes first in OAM
+pub(super) fn obj_line(
+    vram: &[u32],
+    palette: &[u32],
+    objects: &[OamAttribute],
+    params: &[RotationScaleParameter],
+    disp_control: &DisplayControl,
+    mosaic: &Mosaic,
+    y: usize,
+) -> ObjLine {
+    let mut line = ObjLine {
+        pixels: [None; WIDTH],
+        window: [false; WIDTH],
+    };
+    let mut cycles_left = if disp_control.h_blank_interval_free {
+        OBJ_CYCLES_PER_LINE_H_BLANK_FREE
+    } else {
+        OBJ_CYCLES_PER_LINE
+    };
+
+    for obj in objects {
+        let (width, height) = (obj.obj_shape.w, obj.obj_shape.h);
+        // Affine sprites take 10 cycles to set up, and 2 cycles per pixel of the box
+        let (box_w, box_h, cycles) = match (&obj.object_flag, &obj.transformation) {
+            (ObjectFlag::Disbale(true), _) => continue,
+            (ObjectFlag::DoublSize(true), _) => (width * 2, height * 2, 10 + width * 4),
+            (_, Transformation::RotScale { .. }) => (width, height, 10 + width * 2),
+            (_, Transformation::Flip { .. }) => (width, height, width),
+        };
+        // NOTE: Mode 3 is prohibited
+        if obj.obj_mode > OBJ_MODE_WINDOW {
+            continue;
+        }
+
+        let Some(row) = obj_row(obj, y, box_h) else {
+            continue;
+        };
+
+        // Every sprite on the line costs cycles, even the ones that are off screen
+        if cycles_left < cycles {
+            break;
+        }
+        cycles_left -= cycles;
+
+        // The mosaic blocks follow the screen, not the sprite. Vertically that means the
+        // sprite repeats its row from the first line of the block, or its top row if it
+        // starts partway through the block
+        let row = if obj.obj_mosaic {
+            row.saturating_sub((y % mosaic.obj_v) as u32)
+        } else {
+            row
+        };
+
+        let obj_x = obj_x(obj);
+        for col in 0..box_w {
+            let screen_x = obj_x + col as i32;
+            if !(0..WIDTH as i32).contains(&screen_x) {
+                continue;
+            }
+            let is_window = obj.obj_mode == OBJ_MODE_WINDOW;
+            let pixel = &mut line.pixels[screen_x as usize];
+            if !is_window && pixel.is_some_and(|p| p.priority <= obj.priority) {
+                continue;
+            }
+
+            let col = if obj.obj_mosaic {
+                col.saturating_sub(screen_x as u32 % mosaic.obj_h as u32)
+            } else {
+                col
+            };
+
+            let texel = match &obj.transformation {
+                Transformation::Flip { horizontal, veritical } => Some((
+                    if *horizontal { width - 1 - col } else { col },
+                    if *veritical { height - 1 - row } else { row },
+                )),
+                Transformation::RotScale { idx } => {
+                    affine_texel(&params[*idx], col, row, (box_w, box_h), (width, height))
+                }
+            };
+            let Some((tex_x, tex_y)) = texel else {
+                continue;
+            };
+
+            let color_id = obj_color_id(vram, obj, disp_control, tex_x, tex_y);
+            if color_id == 0 {
+                continue;
+            }
+            // OBJ window sprites aren't drawn, they only mark the pixels inside the window
+            if is_window {
+                line.window[screen_x as usize] = true;
+                continue;
+            }
+
+            let palette_idx = if obj.is_256_color {
+                color_id
+            } else {
+                obj.palette_idx * 16 + color_id
+            };
+            *pixel = Some(ObjPixel {
+                color: palette[OBJ_PALETTE_OFFSET + palette_idx],
+                priority: obj.priority,
+                semi_transparent: obj.obj_mode == OBJ_MODE_SEMI_TRANSPARENT,
+            });
+        }
+    }
+
+    line
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{obj_line, ObjPixel};
+    use crate::ppu::disp_control::DisplayControl;
+    use crate::ppu::oam_attribute::{OamAttribute, RotationScaleParameter};
+    use crate::ppu::{Mosaic, WIDTH};
+
+    fn test_vram() -> (Vec<u32>, Vec<u32>) {
+        let mut vram = vec![0; 0x18000 / 4];
+        let mut palette = vec![0; 512];
+        // OBJ palette 1, color 1
+        palette[256 + 17] = 0x7c00;
+        // Tile 2 has color 1 in the top left pixel
+        vram[(0x10000 + 2 * 32) / 4] = 0x1;
+        (vram, palette)
+    }
+
+    #[test]
+    fn obj_line_1d_mapping_and_flip() {
+        let (vram, palette) = test_vram();
+        // 16x16 at (-8 + 512, 250) with tile 0, palette 1, h and v flip
+        let obj = OamAttribute::from(&[0x71f8_00fa, 0x1000][..]);
+
+        // 1D mapping puts tile 2 at the start of the second row of tiles. Flipped, that
+        // pixel is on the right, on the second line after wrapping around
+        let line = obj_line(&vram, &palette, &[obj], &[], &DisplayControl::from(0x1040), &Mosaic::from(0), 1);
+        assert_eq!(Some(ObjPixel { color: 0x7c00, priority: 0, semi_transparent: false }), line.pixels[7]);
+        assert_eq!(None, line.pixels[6]);
+
+        // 2D mapping has tile 32 there instead
+        let obj = OamAttribute::from(&[0x71f8_00fa, 0x1000][..]);
+        let line = obj_line(&vram, &palette, &[obj], &[], &DisplayControl::from(0x1000), &Mosaic::from(0), 1);
+        assert_eq!(None, line.pixels[7]);
+    }
+
+    #[test]
+    fn obj_line_priority_and_disable() {
+        let (vram, palette) = test_vram();
+        let disp_control = DisplayControl::from(0x1040);
+        let disabled = OamAttribute::from(&[0x0000_0200, 0x1002][..]);
+        let back = OamAttribute::from(&[0x0000_0000, 0x1c02][..]);
+        let front = OamAttribute::from(&[0x0000_0400, 0x1402][..]);
+
+        let line = obj_line(&vram, &palette, &[disabled, back, front], &[], &disp_control, &Mosaic::from(0), 0);
+        assert_eq!(Some(ObjPixel { color: 0x7c00, priority: 1, semi_transparent: true }), line.pixels[0]);
+    }
+
+    #[test]
+    fn obj_line_bitmap_mode_obj_vram() {
+        let (vram, palette) = test_vram();
+        let obj = OamAttribute::from(&[0x0000_0000, 0x1002][..]);
+
+        // Tile 2 is part of the frame buffer in mode 3
+        let line = obj_line(&vram, &palette, &[obj], &[], &DisplayControl::from(0x1043), &Mosaic::from(0), 0);
+        assert_eq!(None, line.pixels[0]);
+    }
+
+    #[test]
+    fn obj_line_affine_double_size() {
+        let (vram, palette) = test_vram();
+        // 8x8 with tile 2, affine with parameter 0, double size
+        let obj = OamAttribute::from(&[0x0000_0300, 0x1002][..]);
+        let identity = RotationScaleParameter { dx: 0x100, dmx: 0, dy: 0, dmy: 0x100 };
+        // Flips horizontally and scales to half the size
+        let mirror = RotationScaleParameter { dx: -0x200, dmx: 0, dy: 0, dmy: 0x200 };
+        let disp_control = DisplayControl::from(0x1040);
+
+        // The 8x8 sprite sits in the middle of the 16x16 box
+        let line = obj_line(&vram, &palette, &[obj], &[identity, mirror], &disp_control, &Mosaic::from(0), 4);
+        assert_eq!(None, line.pixels[0]);
+        assert_eq!(None, line.pixels[3]);
+        assert_eq!(Some(0x7c00), line.pixels[4].map(|p| p.color));
+
+        // Parameter 1 puts the top left pixel to the right of the center
+        let obj = OamAttribute::from(&[0x0200_0300, 0x1002][..]);
+        let identity = RotationScaleParameter { dx: 0x100, dmx: 0, dy: 0, dmy: 0x100 };
+        let mirror = RotationScaleParameter { dx: -0x200, dmx: 0, dy: 0, dmy: 0x200 };
+        let line = obj_line(&vram, &palette, &[obj], &[identity, mirror], &disp_control, &Mosaic::from(0), 6);
+        assert_eq!(Some(0x7c00), line.pixels[10].map(|p| p.color));
+        assert_eq!(None, line.pixels[9]);
+        assert_eq!(None, line.pixels[11]);
+    }
+
+    #[test]
+    fn obj_line_obj_window() {
+        let (vram, palette) = test_vram();
+        let obj = OamAttribute::from(&[0x0000_0800, 0x1002][..]);
+
+        let line = obj_line(&vram, &palette, &[obj], &[], &DisplayControl::from(0x1040), &Mosaic::from(0), 0);
+        assert_eq!(None, line.pixels[0]);
+        assert!(line.window[0]);
+        assert!(!line.window[1]);
+    }
+
+    #[test]
+    fn obj_line_mosaic() {
+        let (vram, palette) = test_vram();
+        let disp_control = DisplayControl::from(0x1040);
+        // 8x8 at (1, 1) with tile 2 and mosaic
+        let objs = [OamAttribute::from(&[0x0001_1001, 0x1002][..])];
+        // 3x3 blocks for OBJs
+        let mosaic = Mosaic::from(0x2200);
+
+        // Line 1 starts partway through a block, so it's the top row of the sprite
+        let line = obj_line(&vram, &palette, &objs, &[], &disp_control, &mosaic, 1);
+        assert_eq!(Some(0x7c00), line.pixels[1].map(|p| p.color));
+        assert_eq!(Some(0x7c00), line.pixels[2].map(|p| p.color));
+        // The next block starts at x = 3, with the second column of the sprite
+        assert_eq!(None, line.pixels[3]);
+
+        // Line 2 repeats the top row, line 3 starts a new block
+        let line = obj_line(&vram, &palette, &objs, &[], &disp_control, &mosaic, 2);
+        assert_eq!(Some(0x7c00), line.pixels[2].map(|p| p.color));
+        let line = obj_line(&vram, &palette, &objs, &[], &disp_control, &mosaic, 3);
+        assert_eq!(None, line.pixels[2]);
+    }
+}
@@ -0,0 +1,145 @@
+use crate::utils::expand_5_to_8;
+
+// 256 BG colors, then 256 OBJ colors
+const PALETTE_COLORS: usize = 512;
+const BGR555_COLORS: usize = 0x8000;
+
+// Rows are the output red, green and blue, and columns are how much of the input red,
+// green and blue goes into them. The GBA LCD bleeds the channels into each other a lot
+const GBA_LCD_MIX: [[f64; 3]; 3] = [
+    [0.911, 0.179, 0.0],
+    [0.036, 0.821, 0.107],
+    [0.179, 0.036, 0.786],
+];
+const GBA_SP_MIX: [[f64; 3]; 3] = [
+    [0.96, 0.04, 0.0],
+    [0.02, 0.94, 0.04],
+    [0.02, 0.04, 0.94],
+];
+const DISPLAY_GAMMA: f64 = 2.2;
+
+/// How BGR555 colors are turned into RGB. Games were made for the GBA's screen, so their
+/// colors can look too saturated on a modern display
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorCorrection {
+    /// Each channel is expanded from 5 to 8 bits
+    #[default]
+    Raw,
+    /// The original GBA's dark, unlit screen
+    GbaLcd,
+    /// The GBA SP's frontlit screen, which is close to raw
+    GbaSp,
+}
+
+impl ColorCorrection {
+    /// The profile after this one, wrapping back around to raw
+    pub fn next(self) -> Self {
+        match self {
+            ColorCorrection::Raw => ColorCorrection::GbaLcd,
+            ColorCorrection::GbaLcd => ColorCorrection::GbaSp,
+            ColorCorrection::GbaSp => ColorCorrection::Raw,
+        }
+    }
+
+    fn to_rgb(self, color: u32) -> [u8; 3] {
+        let channels = [color & 0x1f, (color >> 5) & 0x1f, (color >> 10) & 0x1f];
+        let (lcd_gamma, mix) = match self {
+            ColorCorrection::Raw => return channels.map(expand_5_to_8),
+            ColorCorrection::GbaLcd => (4.0, GBA_LCD_MIX),
+            ColorCorrection::GbaSp => (DISPLAY_GAMMA, GBA_SP_MIX),
+        };
+
+        let linear = channels.map(|c| (c as f64 / 31.0).powf(lcd_gamma));
+        mix.map(|row| {
+            let c: f64 = row.iter().zip(linear).map(|(m, c)| m * c).sum();
+            (c.clamp(0.0, 1.0).powf(1.0 / DISPLAY_GAMMA) * 255.0).round() as u8
+        })
+    }
+}
+
+/// PAL RAM decoded into a BGR555 color per entry, which is only reloaded after PAL RAM
+/// is written, and the RGB of every BGR555 color for the current color correction
+#[derive(Debug)]
+pub(super) struct Palette {
+    colors: Vec<u32>,
+    stale: bool,
+    correction: ColorCorrection,
+    rgb: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    pub fn new(correction: ColorCorrection) -> Self {
+        Palette {
+            colors: vec![0; PALETTE_COLORS],
+            stale: true,
+            correction,
+            rgb: rgb_table(correction),
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    /// The BG colors followed by the OBJ colors
+    pub fn colors(&mut self, pal_ram: &[u32]) -> &[u32] {
+        if self.stale {
+            for (i, color) in self.colors.iter_mut().enumerate() {
+                *color = (pal_ram[i >> 1] >> ((i & 1) * 16)) & 0x7fff;
+            }
+            self.stale = false;
+        }
+        &self.colors
+    }
+
+    pub fn rgb(&self, color: u32) -> [u8; 3] {
+        self.rgb[(color & 0x7fff) as usize]
+    }
+
+    pub fn correction(&self) -> ColorCorrection {
+        self.correction
+    }
+
+    pub fn set_correction(&mut self, correction: ColorCorrection) {
+        if correction != self.correction {
+            self.correction = correction;
+            self.rgb = rgb_table(correction);
+        }
+    }
+}
+
+fn rgb_table(correction: ColorCorrection) -> Vec<[u8; 3]> {
+    (0..BGR555_COLORS as u32).map(|c| correction.to_rgb(c)).collect()
+}
+
+mod test {
+    #![allow(unused)]
+    use super::{ColorCorrection, Palette};
+
+    #[test]
+    fn palette_reloads_after_invalidate() {
+        let mut palette = Palette::new(ColorCorrection::Raw);
+        let mut pal_ram = vec![0; 256];
+        pal_ram[0] = 0x7c00_801f;
+        assert_eq!([0x1f, 0x7c00], palette.colors(&pal_ram)[0..2]);
+
+        pal_ram[0] = 0;
+        assert_eq!(0x1f, palette.colors(&pal_ram)[0]);
+        palette.invalidate();
+        assert_eq!(0, palette.colors(&pal_ram)[0]);
+    }
+
+    #[test]
+    fn palette_rgb_corrections() {
+        let mut palette = Palette::new(ColorCorrection::Raw);
+        // 5 bit channels are expanded to the full 8 bits
+        assert_eq!([0xff, 0x84, 0x00], palette.rgb(0x021f));
+        assert_eq!([0xff, 0xff, 0xff], palette.rgb(0xffff));
+
+        // Black stays black, but the GBA LCD bleeds pure red into the other channels
+        palette.set_correction(ColorCorrection::GbaLcd);
+        assert_eq!([0, 0, 0], palette.rgb(0));
+        let [r, g, b] = palette.rgb(0x001f);
+        assert!(r > g && g > 0 && b > g);
+    }
+}
@@ -1,4 +1,20 @@
-use crate::utils::Bitable;
+use super::disp_control::DisplayControl;
+use crate::utils::io_registers::{WIN0_H, WIN0_V, WIN_IN};
+use crate::{
+    gba::system::SystemMemory,
+    memory::{MemoryError, Memory},
+    utils::Bitable,
+};
+
+// Used for every pixel when none of the windows are enabled
+const ALL_VISIBLE: InternalWindowCnt = InternalWindowCnt {
+    bg0: true,
+    bg1: true,
+    bg2: true,
+    bg3: true,
+    obj: true,
+    color_special: true,
+};
 
 pub(super) struct WindowDimensions {
     pub left: u32,
@@ -19,6 +35,22 @@ impl From<(u32, u32)> for WindowDimensions {
     }
 }
 
+impl WindowDimensions {
+    // NOTE: An edge past the screen just runs off of it. When the start is after the
+    // end, the window wraps around the screen instead
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        in_range(x as u32, self.left, self.right) && in_range(y as u32, self.top, self.bottom)
+    }
+}
+
+fn in_range(value: u32, start: u32, end: u32) -> bool {
+    if start <= end {
+        start <= value && value < end
+    } else {
+        value >= start || value < end
+    }
+}
+
 pub(super) struct InternalWindowCnt {
     pub bg0: bool,
     pub bg1: bool,
@@ -41,6 +73,17 @@ impl From<u32> for InternalWindowCnt {
     }
 }
 
+impl InternalWindowCnt {
+    pub fn bg(&self, bg: usize) -> bool {
+        match bg {
+            0 => self.bg0,
+            1 => self.bg1,
+            2 => self.bg2,
+            _ => self.bg3,
+        }
+    }
+}
+
 pub(super) struct WindowCnt {
     pub window_0: InternalWindowCnt,
     pub window_1: InternalWindowCnt,
@@ -58,3 +101,72 @@ impl From<u32> for WindowCnt {
         }
     }
 }
+
+/// The windows enabled in DISPCNT, and what is visible in each of them
+pub(super) struct Windows {
+    window_0: Option<WindowDimensions>,
+    window_1: Option<WindowDimensions>,
+    obj_window: bool,
+    control: WindowCnt,
+}
+
+impl Windows {
+    pub fn new(ram: &SystemMemory, disp_control: &DisplayControl) -> Result<Self, MemoryError> {
+        let h = ram.read_word(WIN0_H)?;
+        let v = ram.read_word(WIN0_V)?;
+        Ok(Windows {
+            window_0: disp_control
+                .display_window0
+                .then(|| WindowDimensions::from((h, v))),
+            window_1: disp_control
+                .display_window1
+                .then(|| WindowDimensions::from((h >> 16, v >> 16))),
+            obj_window: disp_control.display_window_obj,
+            control: WindowCnt::from(ram.read_word(WIN_IN)?),
+        })
+    }
+
+    /// What is visible at a pixel. WIN0 is checked first, then WIN1, then the OBJ window,
+    /// and anything outside of those uses WINOUT
+    pub fn at(&self, x: usize, y: usize, in_obj_window: bool) -> &InternalWindowCnt {
+        if self.window_0.is_none() && self.window_1.is_none() && !self.obj_window {
+            &ALL_VISIBLE
+        } else if self.window_0.as_ref().is_some_and(|w| w.contains(x, y)) {
+            &self.control.window_0
+        } else if self.window_1.as_ref().is_some_and(|w| w.contains(x, y)) {
+            &self.control.window_1
+        } else if self.obj_window && in_obj_window {
+            &self.control.obj
+        } else {
+            &self.control.outside
+        }
+    }
+}
+
+mod test {
+    #![allow(unused)]
+    use super::WindowDimensions;
+
+    #[test]
+    fn window_contains() {
+        // (10, 20) to (100, 50)
+        let window = WindowDimensions::from((0x0a64, 0x1432));
+        assert!(window.contains(10, 20));
+        assert!(!window.contains(100, 20));
+        assert!(!window.contains(50, 50));
+    }
+
+    #[test]
+    fn window_contains_quirks() {
+        // Inverted horizontally, and past the bottom of the screen
+        let window = WindowDimensions::from((0xc810, 0x80ff));
+        assert!(window.contains(210, 159));
+        assert!(window.contains(5, 128));
+        assert!(!window.contains(100, 128));
+        assert!(!window.contains(5, 127));
+
+        // Past the right side of the screen
+        let window = WindowDimensions::from((0x00ff, 0x00a0));
+        assert!(window.contains(239, 0));
+    }
+}
@@ -0,0 +1,64 @@
+use crate::gba::apu::{SampleBuffer, SAMPLE_RATE};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tracing::{event, Level};
+
+// About a tenth of a second, anything queued past it is dropped so the sound doesn't lag
+const MAX_QUEUED_SAMPLES: usize = SAMPLE_RATE as usize / 10;
+
+/// Plays the mixed samples on the default output device
+pub struct AudioOutput {
+    queue: Arc<Mutex<VecDeque<[i16; 2]>>>,
+    // The stream stops once it's dropped
+    _stream: cpal::Stream,
+}
+
+impl AudioOutput {
+    /// Opens a stereo stream at `SAMPLE_RATE`
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("No audio output device")?;
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: cpal::SampleRate(SAMPLE_RATE),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        let queue: Arc<Mutex<VecDeque<[i16; 2]>>> =
+            Arc::new(Mutex::new(VecDeque::with_capacity(MAX_QUEUED_SAMPLES)));
+        let stream_queue = Arc::clone(&queue);
+        let stream = device.build_output_stream(
+            &config,
+            move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                let mut queue = stream_queue.lock().unwrap();
+                for frame in data.chunks_exact_mut(2) {
+                    // NOTE: Silence is played when the emulator falls behind
+                    let sample = queue.pop_front().unwrap_or_default();
+                    frame.copy_from_slice(&sample);
+                }
+            },
+            |e| event!(Level::WARN, "Audio stream error: {}", e),
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(AudioOutput {
+            queue,
+            _stream: stream,
+        })
+    }
+
+    /// Moves every sample in `buffer` to the stream
+    pub fn play(&self, buffer: &mut SampleBuffer) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.extend(buffer.drain());
+        if queue.len() > MAX_QUEUED_SAMPLES {
+            let excess = queue.len() - MAX_QUEUED_SAMPLES;
+            queue.drain(..excess);
+        }
+    }
+}
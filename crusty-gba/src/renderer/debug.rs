@@ -48,13 +48,17 @@ pub fn run_debug(
             }
             DebuggerCommand::Continue(ContinueSubcommand::Endless) => {
                 cpu.tick(&mut memory);
+                cpu.run_dma(&mut memory);
+                memory.tick(cpu.cycles());
                 ppu.tick(cpu.cycles(), &mut memory);
 
                 while !break_points.contains(&cpu.instruction_address()) {
                     cpu.tick(&mut memory);
+                    cpu.run_dma(&mut memory);
+                    memory.tick(cpu.cycles());
                     if ppu.tick(cpu.cycles(), &mut memory) {
                         println!("{}", cpu);
-                        let _ = ppu.get_next_frame(&memory);
+                        let _ = ppu.get_next_frame();
                     }
                 }
                 println!("{}", cpu);
@@ -63,8 +67,10 @@ pub fn run_debug(
                 let mut n = 0;
                 while !break_points.contains(&cpu.instruction_address()) && l > n {
                     cpu.tick(&mut memory);
+                    cpu.run_dma(&mut memory);
+                    memory.tick(cpu.cycles());
                     if ppu.tick(cpu.cycles(), &mut memory) {
-                        let _ = ppu.get_next_frame(&memory);
+                        let _ = ppu.get_next_frame();
                         println!("{}", cpu);
                     };
 
@@ -73,8 +79,10 @@ pub fn run_debug(
             }
             DebuggerCommand::Next => {
                 cpu.tick(&mut memory);
+                cpu.run_dma(&mut memory);
+                memory.tick(cpu.cycles());
                 if ppu.tick(cpu.cycles(), &mut memory) {
-                    let _ = ppu.get_next_frame(&memory);
+                    let _ = ppu.get_next_frame();
                 }
 
                 println!("{}", cpu);
@@ -1,6 +1,10 @@
 use crate::gba::cpu::Cpu;
+use crate::gba::keypad::Button;
 use crate::gba::system::SystemMemory;
 use crate::ppu::Ppu;
+#[cfg(feature = "audio")]
+use crate::renderer::audio::AudioOutput;
+use crate::renderer::SaveFile;
 use std::time::Instant;
 use tracing::{event, Level};
 use tracing_subscriber::filter::{LevelFilter, Targets};
@@ -18,16 +22,94 @@ use winit_input_helper::WinitInputHelper;
 
 const WIDTH: u32 = 240;
 const HEIGHT: u32 = 160;
+// The save is flushed about every second while it's being written
+const SAVE_FLUSH_FRAMES: u32 = 60;
+
+/// Which keyboard key drives each GBA button
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMapping {
+    bindings: Vec<(KeyCode, Button)>,
+}
+
+impl Default for KeyMapping {
+    fn default() -> Self {
+        KeyMapping {
+            bindings: vec![
+                (KeyCode::KeyX, Button::A),
+                (KeyCode::KeyZ, Button::B),
+                (KeyCode::Backspace, Button::Select),
+                (KeyCode::Enter, Button::Start),
+                (KeyCode::ArrowRight, Button::Right),
+                (KeyCode::ArrowLeft, Button::Left),
+                (KeyCode::ArrowUp, Button::Up),
+                (KeyCode::ArrowDown, Button::Down),
+                (KeyCode::KeyS, Button::R),
+                (KeyCode::KeyA, Button::L),
+            ],
+        }
+    }
+}
+
+impl KeyMapping {
+    /// Replaces the key for `button`
+    pub fn bind(&mut self, button: Button, key: KeyCode) {
+        self.bindings.retain(|(_, b)| *b != button);
+        self.bindings.push((key, button));
+    }
+
+    fn update(&self, input: &WinitInputHelper, memory: &mut SystemMemory) {
+        for (key, button) in self.bindings.iter() {
+            if input.key_pressed(*key) {
+                memory.set_button(*button, true);
+            } else if input.key_released(*key) {
+                memory.set_button(*button, false);
+            }
+        }
+    }
+}
+
+/// Parses a `BUTTON=KEY` binding, like `a=KeyJ` or `start=Space`
+pub fn parse_key_binding(binding: &str) -> Result<(Button, KeyCode), String> {
+    let (button, key) = binding
+        .split_once('=')
+        .ok_or(format!("Expected BUTTON=KEY, got {}", binding))?;
+    let button: Button = button
+        .parse()
+        .map_err(|_| format!("Unknown button: {}", button))?;
+    let key = key_code_from_name(key).ok_or(format!("Unknown key: {}", key))?;
+    Ok((button, key))
+}
+
+// NOTE: Only the keys that make sense for a controller are supported
+fn key_code_from_name(name: &str) -> Option<KeyCode> {
+    const KEYS: [KeyCode; 52] = [
+        KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE,
+        KeyCode::KeyF, KeyCode::KeyG, KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ,
+        KeyCode::KeyK, KeyCode::KeyL, KeyCode::KeyM, KeyCode::KeyN, KeyCode::KeyO,
+        KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyT,
+        KeyCode::KeyU, KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX, KeyCode::KeyY,
+        KeyCode::KeyZ, KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3,
+        KeyCode::Digit4, KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8,
+        KeyCode::Digit9, KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft,
+        KeyCode::ArrowRight, KeyCode::Enter, KeyCode::Backspace, KeyCode::Space, KeyCode::Tab,
+        KeyCode::ShiftLeft, KeyCode::ShiftRight, KeyCode::ControlLeft, KeyCode::ControlRight,
+        KeyCode::AltLeft, KeyCode::AltRight, KeyCode::Comma, KeyCode::Period,
+    ];
+    KEYS.into_iter()
+        .find(|k| format!("{:?}", k).eq_ignore_ascii_case(name))
+}
 
 pub fn run_gui(
     mut cpu: Cpu,
     mut memory: SystemMemory,
+    mut ppu: Ppu,
+    key_mapping: KeyMapping,
+    save_file: SaveFile,
     reload_handle: Handle<Targets, Registry>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     event!(Level::INFO, "Runing GUI");
     let event_loop = EventLoop::new().unwrap();
     let mut input = WinitInputHelper::new();
-    let mut ppu = Ppu::default();
 
     let window = {
         let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
@@ -46,6 +128,23 @@ pub fn run_gui(
         Pixels::new(WIDTH, HEIGHT, surface_texture)?
     };
 
+    // NOTE: Without a device the emulator still runs, the samples just aren't played
+    #[cfg(feature = "audio")]
+    let audio = match AudioOutput::new() {
+        Ok(audio) => Some(audio),
+        Err(e) => {
+            event!(Level::WARN, "Unable to open audio output: {}", e);
+            None
+        }
+    };
+
+    let mut frames_since_flush = 0;
+    let flush_save = |memory: &mut SystemMemory| {
+        if let Err(e) = save_file.flush(memory) {
+            event!(Level::ERROR, "Unable to write save file: {}", e);
+        }
+    };
+
     let _res = event_loop.run(|event, elwt| {
         elwt.set_control_flow(winit::event_loop::ControlFlow::Poll);
 
@@ -57,12 +156,14 @@ pub fn run_gui(
                 let current = Instant::now();
                 loop {
                     cpu.tick(&mut memory);
+                    cpu.run_dma(&mut memory);
+                    memory.tick(cpu.cycles());
                     if ppu.tick(cpu.cycles(), &mut memory) {
                         break;
                     }
                 }
                 {
-                    let ppu_buffer = ppu.get_next_frame(&memory);
+                    let ppu_buffer = ppu.get_next_frame();
                     let frame = pixels.frame_mut();
                     let mut i = 0;
                     for pixel in frame.chunks_exact_mut(4) {
@@ -75,6 +176,16 @@ pub fn run_gui(
                     }
                 }
                 let _ = pixels.render();
+                #[cfg(feature = "audio")]
+                if let Some(audio) = &audio {
+                    audio.play(memory.audio_samples());
+                }
+
+                frames_since_flush += 1;
+                if frames_since_flush == SAVE_FLUSH_FRAMES {
+                    frames_since_flush = 0;
+                    flush_save(&mut memory);
+                }
                 // TODO: This seems wrong?
                 let dt = Instant::now() - current;
                 if dt.as_secs_f64() > 0.0 {
@@ -87,10 +198,17 @@ pub fn run_gui(
         if input.update(&event) {
             // Close events
             if input.key_pressed(KeyCode::Escape) || input.close_requested() {
+                flush_save(&mut memory);
                 elwt.exit();
                 return;
             }
-            if input.key_pressed(KeyCode::Space) {
+            key_mapping.update(&input, &mut memory);
+            if input.key_pressed(KeyCode::F2) {
+                let correction = ppu.color_correction().next();
+                event!(Level::INFO, "Color correction: {:?}", correction);
+                ppu.set_color_correction(correction);
+            }
+            if input.key_pressed(KeyCode::F3) {
                 let _ = reload_handle.modify(|filter| {
                     *filter = Targets::default().with_target("crusty_gba", LevelFilter::DEBUG)
                 });
@@ -0,0 +1,119 @@
+use crate::gba::apu::{CHANNELS, SAMPLE_RATE};
+use crate::gba::cpu::Cpu;
+use crate::gba::system::SystemMemory;
+use crate::ppu::Ppu;
+use crate::renderer::SaveFile;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use tracing::{event, Level};
+
+// The suffix of each stem's file, in the order of the mixer's channels
+const STEM_NAMES: [&str; CHANNELS] = ["square1", "square2", "wave", "noise", "fifo_a", "fifo_b"];
+
+/// Runs `frames` frames without a window. The sound goes to a WAV file at `wav`, and with
+/// `stems` each channel also goes to its own file, e.g. `out.wav` and `out.square1.wav`. The
+/// save is flushed once the frames are done
+pub fn run_headless(
+    mut cpu: Cpu,
+    mut memory: SystemMemory,
+    mut ppu: Ppu,
+    save_file: SaveFile,
+    frames: u32,
+    wav: Option<&Path>,
+    stems: bool,
+) -> io::Result<()> {
+    event!(Level::INFO, "Running {} frames headless", frames);
+    if stems {
+        memory.record_stems();
+    }
+
+    let mut samples = Vec::new();
+    let mut stem_samples = vec![Vec::new(); CHANNELS];
+    for _ in 0..frames {
+        loop {
+            cpu.tick(&mut memory);
+            cpu.run_dma(&mut memory);
+            memory.tick(cpu.cycles());
+            if ppu.tick(cpu.cycles(), &mut memory) {
+                break;
+            }
+        }
+        // The same samples the GUI gets, drained every frame so none are dropped
+        samples.extend(memory.audio_samples().drain());
+        for (stem, buffer) in stem_samples.iter_mut().zip(memory.stem_samples()) {
+            stem.extend(buffer.drain());
+        }
+    }
+
+    save_file.flush(&mut memory)?;
+
+    let Some(wav) = wav else {
+        return Ok(());
+    };
+    write_wav(&mut BufWriter::new(File::create(wav)?), &samples)?;
+    if stems {
+        for (name, stem) in STEM_NAMES.iter().zip(stem_samples) {
+            let path = wav.with_extension(format!("{}.wav", name));
+            write_wav(&mut BufWriter::new(File::create(path)?), &stem)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes stereo samples, left first, as a 16 bit PCM WAV file
+fn write_wav(writer: &mut impl Write, samples: &[[i16; 2]]) -> io::Result<()> {
+    const CHANNELS: u16 = 2;
+    const BYTES_PER_SAMPLE: u16 = 2;
+    let block_align = CHANNELS * BYTES_PER_SAMPLE;
+    let data_size = samples.len() as u32 * block_align as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&CHANNELS.to_le_bytes())?;
+    writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    writer.write_all(&(SAMPLE_RATE * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples.iter().flatten() {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+mod test {
+    #![allow(unused)]
+    use super::write_wav;
+
+    #[test]
+    fn write_wav_header_and_samples() {
+        let mut wav = Vec::new();
+        write_wav(&mut wav, &[[1, -1], [0x1234, -0x8000]]).unwrap();
+
+        assert_eq!(44 + 8, wav.len());
+        assert_eq!(b"RIFF", &wav[0..4]);
+        // 36 bytes of header after the size, and 8 bytes of data
+        assert_eq!([44, 0, 0, 0], wav[4..8]);
+        assert_eq!(b"WAVEfmt ", &wav[8..16]);
+        // 16 byte chunk, PCM, 2 channels
+        assert_eq!([16, 0, 0, 0, 1, 0, 2, 0], wav[16..24]);
+        // 48000 Hz, 192000 bytes a second
+        assert_eq!([0x80, 0xbb, 0, 0, 0x00, 0xee, 0x02, 0], wav[24..32]);
+        // 4 byte blocks of 16 bit samples
+        assert_eq!([4, 0, 16, 0], wav[32..36]);
+        assert_eq!(b"data", &wav[36..40]);
+        assert_eq!([8, 0, 0, 0], wav[40..44]);
+        // Little endian, left first
+        assert_eq!([0x01, 0x00, 0xff, 0xff, 0x34, 0x12, 0x00, 0x80], wav[44..]);
+    }
+}
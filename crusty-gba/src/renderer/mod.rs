@@ -1,7 +1,13 @@
+#[cfg(feature = "audio")]
+pub mod audio;
 pub mod debug;
 pub mod gui;
+pub mod headless;
 pub mod ratatui;
+pub mod save_file;
 
 pub use debug::run_debug;
-pub use gui::run_gui;
+pub use gui::{parse_key_binding, run_gui, KeyMapping};
+pub use headless::run_headless;
 pub use ratatui::run_ratatui;
+pub use save_file::SaveFile;
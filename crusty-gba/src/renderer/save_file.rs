@@ -0,0 +1,38 @@
+use crate::gba::system::SystemMemory;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The .sav file next to the ROM, which holds the contents of the backup chip
+#[derive(Debug, Clone, PartialEq)]
+pub struct SaveFile {
+    path: PathBuf,
+}
+
+impl SaveFile {
+    /// The save for `game`, e.g. `game.sav` for `game.gba`
+    pub fn for_game(game: &Path) -> Self {
+        SaveFile {
+            path: game.with_extension("sav"),
+        }
+    }
+
+    /// Loads the save into the backup chip, a missing file starts a fresh save
+    pub fn load(&self, memory: &mut SystemMemory) -> io::Result<()> {
+        match fs::read(&self.path) {
+            Ok(save) => memory.load_save_data(&save),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Writes out the backup chip if it's been written since the last flush
+    pub fn flush(&self, memory: &mut SystemMemory) -> io::Result<()> {
+        if !memory.take_backup_write() {
+            return Ok(());
+        }
+        fs::write(&self.path, memory.save_data())
+    }
+}
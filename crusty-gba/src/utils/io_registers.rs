@@ -27,8 +27,8 @@ pub const BG3_DMY: usize = 0x4000036;
 pub const BG3_POINT_X: usize = 0x4000038;
 pub const BG3_POINT_Y: usize = 0x400003c;
 pub const WIN0_H: usize = 0x4000040;
-pub const WIN0_V: usize = 0x4000042;
-pub const WIN1_H: usize = 0x4000044;
+pub const WIN1_H: usize = 0x4000042;
+pub const WIN0_V: usize = 0x4000044;
 pub const WIN1_V: usize = 0x4000046;
 pub const WIN_IN: usize = 0x4000048;
 pub const WIN_OUT: usize = 0x400004a;
@@ -20,6 +20,12 @@ pub fn bit_is_one_at(num: u32, x: u32) -> bool {
     (num >> x) & 1 == 1
 }
 
+/// Scales a 5 bit color channel to 8 bits, so 31 becomes 255
+pub fn expand_5_to_8(channel: u32) -> u8 {
+    let channel = channel & 0x1f;
+    ((channel << 3) | (channel >> 2)) as u8
+}
+
 pub trait Bitable {
     fn bit_is_high(&self, x: u32) -> bool;
     fn half_byte_at(&self, x: u32) -> u32;
@@ -27,10 +33,6 @@ pub trait Bitable {
     fn halfword_at(&self, x: u32) -> u32;
 }
 
-pub trait BittableColor {
-    fn to_8bit_color(&self) -> ((u8, u8, u8), (u8, u8, u8));
-}
-
 impl Bitable for i32 {
     fn bit_is_high(&self, x: u32) -> bool {
         if x > 31 {
@@ -106,21 +108,3 @@ fn guard_shift(val: u32, x: u32) -> u32 {
     }
     val >> x
 }
-
-impl BittableColor for u32 {
-    // TODO: This is gross, find better way
-    fn to_8bit_color(&self) -> ((u8, u8, u8), (u8, u8, u8)) {
-        (
-            (
-                (self.byte_at(0) * 4) as u8,
-                (self.byte_at(5) * 4) as u8,
-                (self.byte_at(10) * 4) as u8,
-            ),
-            (
-                (self.byte_at(16) * 4) as u8,
-                (self.byte_at(21) * 4) as u8,
-                (self.byte_at(26) * 4) as u8,
-            ),
-        )
-    }
-}